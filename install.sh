@@ -1,7 +1,8 @@
 SHARED_PATH="$HOME/.local/share/roxy"
 
-rm -rf $SHARED_PATH
-cp -r ./roxy $SHARED_PATH
+# ユーザーが追加したテンプレートやstore.dbを残すために、同梱しているファイルだけを上書きする
+mkdir -p "$SHARED_PATH/templates"
+cp ./roxy/template.compose.yml ./roxy/template.dockerfile "$SHARED_PATH"
 
 cargo build --release
 sudo cp target/release/roxy /usr/local/bin
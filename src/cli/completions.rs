@@ -0,0 +1,100 @@
+use std::env;
+use std::ffi::OsStr;
+use std::io;
+
+use clap::builder::StyledStr;
+use clap::{Parser, ValueEnum};
+use clap_complete::engine::CompletionCandidate;
+use clap_complete::env::{Bash, EnvCompleter, Fish, Zsh};
+use log::error;
+
+use crate::domain::repo::{EnvRecord, SharedResources};
+use crate::domain::usecase;
+
+// 補完候補を求めるときにシェルが設定する環境変数
+pub(crate) const COMPLETE_VAR: &str = "ROXY_COMPLETE";
+
+#[derive(Debug, Parser)]
+pub(crate) struct Args {
+    /// Shell to print the completion script for
+    pub shell: ShellArg,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub(crate) enum ShellArg {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+// 補完スクリプトを出力する (スクリプトは補完のたびにこのバイナリを呼び出す)
+pub fn print(shell: ShellArg) -> i32 {
+    let completer: &dyn EnvCompleter = match shell {
+        ShellArg::Bash => &Bash,
+        ShellArg::Zsh => &Zsh,
+        ShellArg::Fish => &Fish,
+    };
+
+    // PATHが変わっても同じバイナリを呼び出せるよう絶対パスを埋め込む
+    let bin = match env::current_exe() {
+        Ok(path) => path.display().to_string(),
+        Err(err) => {
+            error!("Failed to get the path of the executable: {err}");
+            return 1;
+        }
+    };
+
+    if let Err(err) =
+        completer.write_registration(COMPLETE_VAR, "roxy", "roxy", &bin, &mut io::stdout())
+    {
+        error!("Failed to write the completion script: {err}");
+        return 1;
+    }
+    0
+}
+
+pub fn complete_name(current: &OsStr) -> Vec<CompletionCandidate> {
+    env_candidates(current, |record| {
+        (
+            record.spec.project_name.clone(),
+            record.spec.project_path.display().to_string(),
+        )
+    })
+}
+
+pub fn complete_path(current: &OsStr) -> Vec<CompletionCandidate> {
+    env_candidates(current, |record| {
+        (
+            record.spec.project_path.display().to_string(),
+            record.spec.project_name.clone(),
+        )
+    })
+}
+
+// uuidは先頭の数文字を入力すれば補完できる
+pub fn complete_uuid(current: &OsStr) -> Vec<CompletionCandidate> {
+    env_candidates(current, |record| {
+        (
+            record.spec.uuid.to_string(),
+            record.spec.project_name.clone(),
+        )
+    })
+}
+
+// 環境ごとに(候補, 説明)を作り、入力中の文字列から始まるものを候補として返す
+fn env_candidates(
+    current: &OsStr,
+    candidate: impl Fn(&EnvRecord) -> (String, String),
+) -> Vec<CompletionCandidate> {
+    let Some(shared_dir_path) = SharedResources::default_shared_dir_path() else {
+        return Vec::new();
+    };
+    let current = current.to_string_lossy();
+
+    usecase::env_records_for_completion(&SharedResources::new(shared_dir_path))
+        .iter()
+        .map(candidate)
+        .filter(|(value, _)| value.starts_with(current.as_ref()))
+        .map(|(value, help)| CompletionCandidate::new(value).help(Some(StyledStr::from(help))))
+        .collect()
+}
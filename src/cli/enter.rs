@@ -1,11 +1,17 @@
-use std::path::PathBuf;
-
 use clap::Parser;
-use uuid::Uuid;
+
+use super::env::EnvArgs;
 
 #[derive(Debug, Parser)]
 pub(crate) struct Args {
-    pub name: Option<String>,
-    pub path: Option<PathBuf>,
-    pub uuid: Option<Uuid>,
+    #[clap(flatten)]
+    pub env: EnvArgs,
+
+    /// Service of the compose template to use (the primary service if omitted)
+    #[clap(long)]
+    pub service: Option<String>,
+
+    /// Shell to start in the environment (detected from the template or the container if omitted)
+    #[clap(long)]
+    pub shell: Option<String>,
 }
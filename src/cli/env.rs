@@ -0,0 +1,33 @@
+use std::path::PathBuf;
+
+use clap::Args;
+use clap_complete::engine::ArgValueCompleter;
+use uuid::Uuid;
+
+use crate::domain::repo::EnvSpecifier;
+
+use super::completions::{complete_name, complete_path, complete_uuid};
+
+// 操作する環境を指定する引数
+#[derive(Debug, Args)]
+pub(crate) struct EnvArgs {
+    #[clap(add = ArgValueCompleter::new(complete_name))]
+    pub name: Option<String>,
+    #[clap(add = ArgValueCompleter::new(complete_path))]
+    pub path: Option<PathBuf>,
+    #[clap(add = ArgValueCompleter::new(complete_uuid))]
+    pub uuid: Option<Uuid>,
+}
+
+impl EnvArgs {
+    // 指定された引数から環境指定子を作る (name, path, uuidの順に優先する)
+    pub fn into_specifier(self) -> Option<EnvSpecifier> {
+        if let Some(name) = self.name {
+            Some(EnvSpecifier::Name(name))
+        } else if let Some(path) = self.path {
+            Some(EnvSpecifier::Path(path))
+        } else {
+            self.uuid.map(EnvSpecifier::Uuid)
+        }
+    }
+}
@@ -0,0 +1,40 @@
+use std::io::{self, IsTerminal};
+
+use clap::Parser;
+
+use super::env::EnvArgs;
+
+#[derive(Debug, Parser)]
+pub(crate) struct Args {
+    #[clap(flatten)]
+    pub env: EnvArgs,
+
+    /// Service of the compose template to use (the primary service if omitted)
+    #[clap(long)]
+    pub service: Option<String>,
+
+    /// Allocate a pseudo-TTY (default: only when stdin and stdout are terminals)
+    #[clap(short = 't', long, conflicts_with = "no_tty")]
+    pub tty: bool,
+
+    /// Never allocate a pseudo-TTY
+    #[clap(short = 'T', long)]
+    pub no_tty: bool,
+
+    /// Command to run inside the environment
+    #[clap(last = true, required = true)]
+    pub command: Vec<String>,
+}
+
+impl Args {
+    // TTYを割り当てるかどうかを決める
+    pub fn use_tty(&self) -> bool {
+        if self.tty {
+            true
+        } else if self.no_tty {
+            false
+        } else {
+            io::stdin().is_terminal() && io::stdout().is_terminal()
+        }
+    }
+}
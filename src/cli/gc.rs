@@ -0,0 +1,8 @@
+use clap::Parser;
+
+#[derive(Debug, Parser)]
+pub(crate) struct Args {
+    /// Only report what would be removed
+    #[clap(long)]
+    pub dry_run: bool,
+}
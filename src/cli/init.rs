@@ -0,0 +1,72 @@
+use std::path::PathBuf;
+
+use clap::{Parser, ValueEnum};
+
+use crate::domain::config::{NetworkMode, SecurityProfile};
+
+#[derive(Debug, Parser)]
+pub(crate) struct Args {
+    /// Project directory to create the environment for (defaults to the current directory)
+    #[clap(value_name = "PATH", conflicts_with = "path_option")]
+    pub path: Option<PathBuf>,
+
+    /// Same as PATH
+    #[clap(long = "path", value_name = "PATH")]
+    pub path_option: Option<PathBuf>,
+
+    /// Template profile under the shared templates directory (overrides .roxy.toml)
+    #[clap(long)]
+    pub template: Option<String>,
+
+    /// Security profile of the primary service (overrides .roxy.toml; defaults to debug)
+    #[clap(long)]
+    pub security: Option<SecurityArg>,
+
+    /// Network of the primary service (overrides .roxy.toml; defaults to the template's settings)
+    #[clap(long)]
+    pub network: Option<NetworkArg>,
+
+    /// Name of the environment (defaults to the directory name, numbered if already used)
+    #[clap(long)]
+    pub name: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub(crate) enum SecurityArg {
+    /// Default capabilities and seccomp profile of the runtime, no privilege escalation
+    Minimal,
+    /// Allow ptrace and disable seccomp for debugging
+    Debug,
+    /// Run a privileged container
+    Privileged,
+}
+
+impl From<SecurityArg> for SecurityProfile {
+    fn from(arg: SecurityArg) -> Self {
+        match arg {
+            SecurityArg::Minimal => SecurityProfile::Minimal,
+            SecurityArg::Debug => SecurityProfile::Debug,
+            SecurityArg::Privileged => SecurityProfile::Privileged,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub(crate) enum NetworkArg {
+    /// Share the network of the host (ports in the template are dropped)
+    Host,
+    /// Isolated bridge network per environment with the template ports published
+    Bridge,
+    /// No network
+    None,
+}
+
+impl From<NetworkArg> for NetworkMode {
+    fn from(arg: NetworkArg) -> Self {
+        match arg {
+            NetworkArg::Host => NetworkMode::Host,
+            NetworkArg::Bridge => NetworkMode::Bridge,
+            NetworkArg::None => NetworkMode::Disabled,
+        }
+    }
+}
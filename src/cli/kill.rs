@@ -1,11 +1,9 @@
-use std::path::PathBuf;
-
 use clap::Parser;
-use uuid::Uuid;
+
+use super::env::EnvArgs;
 
 #[derive(Debug, Parser)]
 pub(crate) struct Args {
-    pub name: Option<String>,
-    pub path: Option<PathBuf>,
-    pub uuid: Option<Uuid>,
+    #[clap(flatten)]
+    pub env: EnvArgs,
 }
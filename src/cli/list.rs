@@ -0,0 +1,8 @@
+use clap::Parser;
+
+#[derive(Debug, Parser)]
+pub(crate) struct Args {
+    /// Also show creation time, last entered time, template hash and image id
+    #[clap(short, long)]
+    pub long: bool,
+}
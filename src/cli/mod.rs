@@ -1,59 +1,178 @@
+mod completions;
 mod enter;
+mod env;
+mod exec;
+mod gc;
+mod init;
 mod kill;
+mod list;
+mod port;
+mod rebuild;
+mod rename;
+mod start;
+mod status;
+mod stop;
+mod template;
 
-use clap::{Parser, Subcommand};
+use clap::{CommandFactory, Parser, Subcommand, ValueEnum};
+use clap_complete::CompleteEnv;
+use std::io::{self, IsTerminal};
 use std::path::Path;
 
-use crate::domain::repo::{EnvSpecifier, SharedResources};
-use crate::domain::usecase::{self, Action};
+use crate::domain::config::{NetworkMode, SecurityProfile};
+use crate::domain::repo::{RuntimeKind, SharedResources};
+use crate::domain::usecase::{self, Action, Options, OutputFormat};
 
 #[derive(Debug, Parser)]
 struct Args {
+    /// Container runtime used to create new environments (auto-detected if omitted)
+    #[clap(long, global = true, env = "ROXY_RUNTIME")]
+    runtime: Option<RuntimeArg>,
+
+    /// Output format of commands that print environment data
+    #[clap(long, global = true, env = "ROXY_FORMAT", default_value = "table")]
+    format: FormatArg,
+
+    /// Never prompt; fail when the target environment is ambiguous
+    #[clap(long, global = true, env = "ROXY_NO_INPUT")]
+    no_input: bool,
+
     #[clap(subcommand)]
     sub_command: SubCommand,
 }
 
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum RuntimeArg {
+    Docker,
+    Podman,
+    DockerApi,
+}
+
+impl From<RuntimeArg> for RuntimeKind {
+    fn from(arg: RuntimeArg) -> Self {
+        match arg {
+            RuntimeArg::Docker => RuntimeKind::Docker,
+            RuntimeArg::Podman => RuntimeKind::Podman,
+            RuntimeArg::DockerApi => RuntimeKind::DockerApi,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum FormatArg {
+    Table,
+    Json,
+    Yaml,
+    Tsv,
+}
+
+impl From<FormatArg> for OutputFormat {
+    fn from(arg: FormatArg) -> Self {
+        match arg {
+            FormatArg::Table => OutputFormat::Table,
+            FormatArg::Json => OutputFormat::Json,
+            FormatArg::Yaml => OutputFormat::Yaml,
+            FormatArg::Tsv => OutputFormat::Tsv,
+        }
+    }
+}
+
 #[derive(Debug, Subcommand)]
 enum SubCommand {
-    Init,
+    Init(init::Args),
     Enter(enter::Args),
-    List,
-    Kill(enter::Args),
+    List(list::Args),
+    Kill(kill::Args),
+    /// Stop an environment while keeping its container
+    Stop(stop::Args),
+    /// Start a stopped environment
+    Start(start::Args),
+    /// Recreate an environment from the current templates
+    Rebuild(rebuild::Args),
+    /// Rename an environment
+    Rename(rename::Args),
+    /// Show the host ports allocated to the published ports of an environment
+    Port(port::Args),
+    /// Show the actual state of the containers of each environment
+    Status(status::Args),
+    /// Remove config directories, containers and records that belong to no environment
+    Gc(gc::Args),
+    /// Manage template profiles
+    Template(template::Args),
+    /// Run a command inside an environment
+    Exec(exec::Args),
+    /// Print a shell completion script (e.g. `source <(roxy completions bash)`)
+    Completions(completions::Args),
 }
 
 fn cli_subcommand_to_usecase_action(sub_command: SubCommand) -> Action {
     match sub_command {
-        SubCommand::Init => Action::Init,
-        SubCommand::Enter(args) => {
-            if let Some(name) = args.name {
-                Action::Enter(Some(EnvSpecifier::Name(name)))
-            } else if let Some(path) = args.path {
-                Action::Enter(Some(EnvSpecifier::Path(path)))
-            } else if let Some(uuid) = args.uuid {
-                Action::Enter(Some(EnvSpecifier::Uuid(uuid)))
-            } else {
-                Action::Enter(None)
-            }
-        }
-        SubCommand::List => Action::List,
-        SubCommand::Kill(args) => {
-            if let Some(name) = args.name {
-                Action::Kill(Some(EnvSpecifier::Name(name)))
-            } else if let Some(path) = args.path {
-                Action::Kill(Some(EnvSpecifier::Path(path)))
-            } else if let Some(uuid) = args.uuid {
-                Action::Kill(Some(EnvSpecifier::Uuid(uuid)))
-            } else {
-                Action::Kill(None)
+        SubCommand::Init(args) => Action::Init {
+            path: args.path.or(args.path_option),
+            template: args.template,
+            security: args.security.map(SecurityProfile::from),
+            network: args.network.map(NetworkMode::from),
+            name: args.name,
+        },
+        SubCommand::Enter(args) => Action::Enter {
+            specifier: args.env.into_specifier(),
+            service: args.service,
+            shell: args.shell,
+        },
+        SubCommand::List(args) => Action::List { long: args.long },
+        SubCommand::Kill(args) => Action::Kill(args.env.into_specifier()),
+        SubCommand::Stop(args) => Action::Stop(args.env.into_specifier()),
+        SubCommand::Start(args) => Action::Start(args.env.into_specifier()),
+        SubCommand::Rebuild(args) => Action::Rebuild(args.env.into_specifier()),
+        SubCommand::Rename(args) => Action::Rename {
+            specifier: args.env.into_specifier(),
+            name: args.new_name,
+        },
+        SubCommand::Port(args) => Action::Port(args.env.into_specifier()),
+        SubCommand::Status(args) => Action::Status { fix: args.fix },
+        SubCommand::Gc(args) => Action::Gc {
+            dry_run: args.dry_run,
+        },
+        SubCommand::Template(args) => match args.sub_command {
+            template::SubCommand::List => Action::TemplateList,
+        },
+        SubCommand::Exec(args) => {
+            let tty = args.use_tty();
+            Action::Exec {
+                specifier: args.env.into_specifier(),
+                service: args.service,
+                argv: args.command,
+                tty,
             }
         }
+        SubCommand::Completions(_) => unreachable!("completions are handled before usecases"),
     }
 }
 
-pub fn handle(current_path: &Path, shared_resources: &SharedResources) {
+// シェルの補完から呼ばれた場合は候補を出力してプロセスを終了する
+pub fn complete() {
+    CompleteEnv::with_factory(Args::command)
+        .var(completions::COMPLETE_VAR)
+        .complete();
+}
+
+// プロセスの終了コードを返す
+pub fn handle(current_path: &Path, shared_resources: &SharedResources) -> i32 {
     let args = Args::parse();
 
+    // 補完スクリプトの出力は環境を操作しないのでユースケースを経由しない
+    if let SubCommand::Completions(args) = &args.sub_command {
+        return completions::print(args.shell);
+    }
+
+    let options = Options {
+        runtime: args.runtime.map(RuntimeKind::from),
+        format: args.format.into(),
+        // 候補は標準エラー出力に表示し、標準入力から選ばせる
+        interactive: !args.no_input && io::stdin().is_terminal() && io::stderr().is_terminal(),
+    };
+
     let action = cli_subcommand_to_usecase_action(args.sub_command);
 
-    usecase::handle(action, current_path, shared_resources);
+    usecase::handle(action, current_path, shared_resources, &options)
 }
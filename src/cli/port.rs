@@ -0,0 +1,9 @@
+use clap::Parser;
+
+use super::env::EnvArgs;
+
+#[derive(Debug, Parser)]
+pub(crate) struct Args {
+    #[clap(flatten)]
+    pub env: EnvArgs,
+}
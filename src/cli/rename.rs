@@ -0,0 +1,12 @@
+use clap::Parser;
+
+use super::env::EnvArgs;
+
+#[derive(Debug, Parser)]
+pub(crate) struct Args {
+    /// New name of the environment (must not be used by another environment)
+    pub new_name: String,
+
+    #[clap(flatten)]
+    pub env: EnvArgs,
+}
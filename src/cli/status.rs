@@ -0,0 +1,8 @@
+use clap::Parser;
+
+#[derive(Debug, Parser)]
+pub(crate) struct Args {
+    /// Refresh stale container ids and drop environments whose containers are gone
+    #[clap(long)]
+    pub fix: bool,
+}
@@ -0,0 +1,13 @@
+use clap::{Parser, Subcommand};
+
+#[derive(Debug, Parser)]
+pub(crate) struct Args {
+    #[clap(subcommand)]
+    pub sub_command: SubCommand,
+}
+
+#[derive(Debug, Subcommand)]
+pub(crate) enum SubCommand {
+    /// List available template profiles
+    List,
+}
@@ -0,0 +1,208 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+use indexmap::IndexMap;
+use serde::{Deserialize, Serialize};
+
+use super::repo::Error;
+
+// プロジェクトのルートに置く設定ファイルの名前
+pub const PROJECT_CONFIG_NAME: &str = ".roxy.toml";
+
+// プロジェクトごとの設定 (共有ディレクトリのテンプレートの上に重ねて適用される)
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProjectConfig {
+    // テンプレートのプロファイル名、もしくはテンプレートのディレクトリのパス
+    // (ディレクトリはtemplate.dockerfileとtemplate.compose.ymlを含む)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub template: Option<String>,
+
+    // 環境に入るときに使うシェル
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub shell: Option<String>,
+
+    // 主サービスを実行するユーザー (テンプレートのx-roxyのuserより優先する)
+    // "host"ならホストのユーザーと同じUID/GID、それ以外はcomposeのuserとしてそのまま使う
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+
+    // 主サービスに適用するセキュリティプロファイル (指定がない場合はdebug)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub security: Option<SecurityProfile>,
+
+    // 主サービスのネットワーク (指定がない場合はテンプレートの設定を使う)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub network: Option<NetworkMode>,
+
+    // 追加でマウントするボリューム ("host:container[:mode]")
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub mounts: Vec<String>,
+
+    // 追加で公開するポート ("[ip:]host:container[/proto]")
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ports: Vec<String>,
+
+    // 追加の環境変数
+    #[serde(default, skip_serializing_if = "IndexMap::is_empty")]
+    pub environment: IndexMap<String, String>,
+
+    #[serde(default, skip_serializing_if = "Resources::is_empty")]
+    pub resources: Resources,
+
+    // テンプレートの{{name}}に代入する値
+    #[serde(default, skip_serializing_if = "IndexMap::is_empty")]
+    pub variables: IndexMap<String, String>,
+}
+
+// 主サービスの権限の強さ (テンプレートのprivileged、cap_add、cap_drop、security_optを置き換える)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SecurityProfile {
+    // コンテナランタイムの既定の権限のみ (信頼できないバイナリを実行する場合)
+    Minimal,
+    // gdbなどでデバッグできるようにptraceとseccompの制限を外す
+    #[default]
+    Debug,
+    // 特権コンテナ
+    Privileged,
+}
+
+impl SecurityProfile {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SecurityProfile::Minimal => "minimal",
+            SecurityProfile::Debug => "debug",
+            SecurityProfile::Privileged => "privileged",
+        }
+    }
+}
+
+impl fmt::Display for SecurityProfile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+// 主サービスをどのネットワークにつなぐか (テンプレートのnetwork_modeを置き換える)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum NetworkMode {
+    // ホストのネットワークをそのまま使う (portsは意味がないので使わない)
+    Host,
+    // 環境ごとのブリッジネットワークにつなぎ、portsで指定したポートだけを公開する
+    Bridge,
+    // ネットワークにつながない
+    #[serde(rename = "none")]
+    Disabled,
+}
+
+impl NetworkMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            NetworkMode::Host => "host",
+            NetworkMode::Bridge => "bridge",
+            NetworkMode::Disabled => "none",
+        }
+    }
+}
+
+impl fmt::Display for NetworkMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+// コンテナのリソース制限
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Resources {
+    // 使用できるCPUの数 (例: 1.5)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cpus: Option<f64>,
+
+    // メモリの上限 (例: "2g")
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub memory: Option<String>,
+
+    // プロセス数の上限
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pids: Option<i64>,
+}
+
+impl Resources {
+    pub fn is_empty(&self) -> bool {
+        self.cpus.is_none() && self.memory.is_none() && self.pids.is_none()
+    }
+}
+
+impl ProjectConfig {
+    // プロジェクトのルートから設定ファイルを読み込む (存在しない場合は既定の設定を返す)
+    // 相対パスはプロジェクトのルートを基準とした絶対パスに変換する
+    pub fn load(project_path: &Path) -> Result<Self, Error> {
+        let config_path = project_path.join(PROJECT_CONFIG_NAME);
+        let contents = match fs::read_to_string(&config_path) {
+            Ok(c) => c,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(err) => {
+                return Err(Error::Io {
+                    path: Some(config_path),
+                    source: err,
+                });
+            }
+        };
+
+        let mut config = Self::from_toml(&contents).map_err(|err| match err {
+            Error::TomlDe { source, .. } => Error::TomlDe {
+                path: Some(config_path.clone()),
+                source,
+            },
+            err => err,
+        })?;
+
+        if let Some(template) = &config.template
+            && is_template_path(template)
+        {
+            let template = resolve(project_path, Path::new(template));
+            config.template = Some(template.display().to_string());
+        }
+        // "."から始まるホスト側のパスのみを相対パスとみなす (それ以外は名前付きボリュームか絶対パス)
+        for mount in config.mounts.iter_mut() {
+            if let Some((host, rest)) = mount.split_once(':')
+                && host.starts_with('.')
+            {
+                let host = resolve(project_path, Path::new(host));
+                *mount = format!("{}:{rest}", host.display());
+            }
+        }
+
+        Ok(config)
+    }
+
+    pub fn from_toml(contents: &str) -> Result<Self, Error> {
+        toml::from_str(contents).map_err(|err| Error::TomlDe {
+            path: None,
+            source: err,
+        })
+    }
+
+    pub fn to_toml(&self) -> Result<String, Error> {
+        toml::to_string(self).map_err(Error::TomlSer)
+    }
+}
+
+// テンプレートの指定がプロファイル名ではなくパスかどうか
+pub fn is_template_path(template: &str) -> bool {
+    template.contains('/') || template.starts_with('.')
+}
+
+// baseを基準にpathを解決する ("."の要素は取り除く)
+fn resolve(base: &Path, path: &Path) -> PathBuf {
+    let path = path
+        .components()
+        .filter(|c| !matches!(c, Component::CurDir))
+        .collect::<PathBuf>();
+    base.join(path)
+}
@@ -1,29 +1,143 @@
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io;
 use std::path::{Path, PathBuf};
+use std::str::FromStr;
 
+use indexmap::IndexMap;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use tabled::Tabled;
 use uuid::Uuid;
 
+use super::config::{NetworkMode, ProjectConfig, SecurityProfile, is_template_path};
+use crate::util::seconds_since;
+
 pub struct SharedResources {
     pub shared_dir_path: PathBuf,
+    pub templates_relative_path: PathBuf,
     pub dockerfile_template_relative_path: PathBuf,
     pub compose_template_relative_path: PathBuf,
     pub database_relative_path: PathBuf,
+    pub lock_relative_path: PathBuf,
 }
 
+// プロファイルが指定されていない場合に使う、共有ディレクトリ直下のテンプレートの名前
+pub const DEFAULT_TEMPLATE: &str = "default";
+
 impl SharedResources {
-    pub fn dockerfile_template_absolute_path(&self) -> PathBuf {
-        self.shared_dir_path
+    pub fn new(shared_dir_path: PathBuf) -> Self {
+        Self {
+            shared_dir_path,
+            templates_relative_path: PathBuf::from_iter(["templates"]),
+            dockerfile_template_relative_path: PathBuf::from_iter(["template.dockerfile"]),
+            compose_template_relative_path: PathBuf::from_iter(["template.compose.yml"]),
+            database_relative_path: PathBuf::from_iter(["store.db"]),
+            lock_relative_path: PathBuf::from_iter(["roxy.lock"]),
+        }
+    }
+
+    // 共有ディレクトリの場所 (~/.local/share/roxy)
+    pub fn default_shared_dir_path() -> Option<PathBuf> {
+        let mut shared_dir_path = env::home_dir()?;
+        shared_dir_path.extend([".local", "share", "roxy"]);
+        Some(shared_dir_path)
+    }
+
+    // テンプレートの指定からテンプレートのディレクトリを返す
+    // 指定がない場合は共有ディレクトリ、プロファイル名の場合はtemplates/<name>/
+    pub fn template_dir(&self, template: Option<&str>) -> PathBuf {
+        match template {
+            None | Some(DEFAULT_TEMPLATE) => self.shared_dir_path.clone(),
+            Some(path) if is_template_path(path) => PathBuf::from(path),
+            Some(name) => self
+                .shared_dir_path
+                .join(&self.templates_relative_path)
+                .join(name),
+        }
+    }
+
+    // 利用できるテンプレートのプロファイル名の一覧を返す
+    pub fn template_names(&self) -> Result<Vec<String>, Error> {
+        let mut names = vec![DEFAULT_TEMPLATE.to_string()];
+
+        let templates_path = self.shared_dir_path.join(&self.templates_relative_path);
+        let entries = match fs::read_dir(&templates_path) {
+            Ok(e) => e,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(names),
+            Err(err) => {
+                return Err(Error::Io {
+                    path: Some(templates_path),
+                    source: err,
+                });
+            }
+        };
+
+        let mut profiles = Vec::new();
+        for entry in entries {
+            let entry = entry.map_err(|err| Error::Io {
+                path: Some(templates_path.clone()),
+                source: err,
+            })?;
+            if entry.path().is_dir() {
+                profiles.push(entry.file_name().to_string_lossy().to_string());
+            }
+        }
+        profiles.sort();
+        names.extend(profiles);
+
+        Ok(names)
+    }
+
+    pub fn dockerfile_template_absolute_path(&self, template: Option<&str>) -> PathBuf {
+        self.template_dir(template)
             .join(&self.dockerfile_template_relative_path)
     }
 
-    pub fn compose_template_absolute_path(&self) -> PathBuf {
-        self.shared_dir_path
+    pub fn compose_template_absolute_path(&self, template: Option<&str>) -> PathBuf {
+        self.template_dir(template)
             .join(&self.compose_template_relative_path)
     }
 
+    // テンプレートのdockerfileとcompose.ymlの内容のハッシュ (SHA-256の16進表記) を返す
+    pub fn template_hash(&self, template: Option<&str>) -> Result<String, Error> {
+        let mut hasher = Sha256::new();
+        for path in [
+            self.dockerfile_template_absolute_path(template),
+            self.compose_template_absolute_path(template),
+        ] {
+            let contents = match fs::read(&path) {
+                Ok(c) => c,
+                Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                    return Err(Error::TemplateNotFound { path });
+                }
+                Err(err) => {
+                    return Err(Error::Io {
+                        path: Some(path),
+                        source: err,
+                    });
+                }
+            };
+            // ファイルの境界が変わっても同じハッシュにならないように長さも含める
+            hasher.update((contents.len() as u64).to_le_bytes());
+            hasher.update(&contents);
+        }
+
+        Ok(hasher
+            .finalize()
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect())
+    }
+
     pub fn database_absolute_path(&self) -> PathBuf {
         self.shared_dir_path.join(&self.database_relative_path)
     }
+
+    pub fn lock_absolute_path(&self) -> PathBuf {
+        self.shared_dir_path.join(&self.lock_relative_path)
+    }
 }
 
 use thiserror::Error;
@@ -56,22 +170,35 @@ pub enum Error {
     #[error("YAML deserialize error: {0}")]
     YamlDe(#[source] serde_yaml::Error),
 
+    #[error("TOML serialize error: {0}")]
+    TomlSer(#[source] toml::ser::Error),
+
+    #[error("TOML deserialize error {path:?}: {source}")]
+    TomlDe {
+        path: Option<std::path::PathBuf>,
+        #[source]
+        source: toml::de::Error,
+    },
+
     #[error("invalid compose configuration: {reason}")]
     InvalidComposeConfig { reason: String },
 
-    #[error("failed to run command: {cmd} (status: {status:?}){err}")]
+    #[error("failed to run command: {cmd} (status: {status:?}): {err}")]
     Command {
         cmd: String,
         status: Option<i32>,
         err: String,
     },
 
-    #[error("remove failed: {what}")]
-    RemoveFailed { what: String },
-
     #[error("template not found: {path:?}")]
     TemplateNotFound { path: std::path::PathBuf },
 
+    #[error("unknown template variables in {path:?}: {}", names.join(", "))]
+    UnknownTemplateVariables {
+        path: std::path::PathBuf,
+        names: Vec<String>,
+    },
+
     #[error("invalid path: {path:?} ({msg})")]
     InvalidPath {
         path: std::path::PathBuf,
@@ -87,9 +214,41 @@ pub enum Error {
 
     #[error("uuid error: {0}")]
     Uuid(uuid::Error),
+
+    #[error("JSON error: {0}")]
+    Json(#[source] serde_json::Error),
+
+    #[error("docker engine API error (status: {status}): {message}")]
+    Api { status: u16, message: String },
+
+    #[error("unknown runtime: {name}")]
+    UnknownRuntime { name: String },
+
+    #[error("unknown environment state: {name}")]
+    UnknownState { name: String },
+
+    #[error("unsupported database schema version {version} (supported up to {supported})")]
+    UnsupportedSchema { version: usize, supported: usize },
+
+    #[error("host port {port}/{protocol} is already used by {owner}")]
+    PortConflict {
+        port: u16,
+        protocol: String,
+        owner: String,
+    },
+
+    #[error("no free host port in {start}-{end}")]
+    PortsExhausted { start: u16, end: u16 },
+
+    #[error("unknown service: {name} (available: {})", available.join(", "))]
+    UnknownService {
+        name: String,
+        available: Vec<String>,
+    },
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(transparent)]
 pub struct ContainerId {
     id: String,
 }
@@ -99,8 +258,55 @@ impl ContainerId {
         Self { id: id.to_string() }
     }
 
-    pub fn to_string(&self) -> String {
-        self.id.clone()
+    // 短縮されたidと完全なidを同じコンテナとみなして比較する
+    pub fn is_same(&self, other: &ContainerId) -> bool {
+        !self.id.is_empty()
+            && !other.id.is_empty()
+            && (self.id.starts_with(&other.id) || other.id.starts_with(&self.id))
+    }
+}
+
+impl fmt::Display for ContainerId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.id)
+    }
+}
+
+// 環境を作成したコンテナランタイムの種類
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RuntimeKind {
+    Docker,
+    Podman,
+    DockerApi,
+}
+
+impl RuntimeKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RuntimeKind::Docker => "docker",
+            RuntimeKind::Podman => "podman",
+            RuntimeKind::DockerApi => "docker-api",
+        }
+    }
+}
+
+impl fmt::Display for RuntimeKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for RuntimeKind {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "docker" => Ok(RuntimeKind::Docker),
+            "podman" => Ok(RuntimeKind::Podman),
+            "docker-api" => Ok(RuntimeKind::DockerApi),
+            _ => Err(Error::UnknownRuntime { name: s.into() }),
+        }
     }
 }
 
@@ -115,17 +321,161 @@ pub struct EnvSpec {
     pub uuid: Uuid,
     pub project_path: PathBuf,
     pub project_name: String,
+    // 環境の作成時に適用されたプロジェクトの設定
+    pub config: ProjectConfig,
+    // 環境の作成時に使ったテンプレートの内容のハッシュ
+    pub template_hash: Option<String>,
+}
+
+// 環境のライフサイクル上の状態
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum EnvState {
+    Running,
+    Stopped,
+}
+
+impl EnvState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EnvState::Running => "running",
+            EnvState::Stopped => "stopped",
+        }
+    }
+}
+
+impl fmt::Display for EnvState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for EnvState {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "running" => Ok(EnvState::Running),
+            "stopped" => Ok(EnvState::Stopped),
+            _ => Err(Error::UnknownState { name: s.into() }),
+        }
+    }
+}
+
+// コンテナを調べて分かった実際の状態 (後ろのものほど深刻)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ContainerStatus {
+    Running,
+    // 一時停止されている (コンテナエンジンでunpauseするまで入れない)
+    Paused,
+    Exited,
+    Missing,
+}
+
+impl ContainerStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ContainerStatus::Running => "running",
+            ContainerStatus::Paused => "paused",
+            ContainerStatus::Exited => "exited",
+            ContainerStatus::Missing => "missing",
+        }
+    }
+}
+
+impl fmt::Display for ContainerStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct ContainerInfo {
+    // 主サービスのコンテナ
     pub container_id: ContainerId,
+    // サービス名とコンテナの組 (主サービスを含む)
+    // サービスを記録する前に作成された環境では空になる
+    pub services: IndexMap<String, ContainerId>,
+    // 主サービスのコンテナのイメージ
+    pub image_id: Option<String>,
+    pub runtime: RuntimeKind,
+    pub state: EnvState,
+    // ホストに公開しているポート (ポートを記録する前に作成された環境では空になる)
+    pub ports: Vec<PortMapping>,
+}
+
+// サービスのポートとそれを公開しているホストのポートの組
+#[derive(Debug, Clone, PartialEq, Eq, Tabled, Serialize, Deserialize)]
+pub struct PortMapping {
+    pub service: String,
+    pub container_port: u16,
+    pub protocol: String,
+    // 指定がない場合はすべてのアドレスで待ち受ける
+    #[tabled(display = "display_or_dash")]
+    pub host_ip: Option<String>,
+    pub host_port: u16,
+}
+
+impl ContainerInfo {
+    // サービス名からコンテナを返す (指定がない場合は主サービスのコンテナ)
+    pub fn container(&self, service: Option<&str>) -> Result<&ContainerId, Error> {
+        let Some(service) = service else {
+            return Ok(&self.container_id);
+        };
+        self.services
+            .get(service)
+            .ok_or_else(|| Error::UnknownService {
+                name: service.into(),
+                available: self.services.keys().cloned().collect(),
+            })
+    }
+
+    // 環境に属するすべてのコンテナを返す
+    pub fn container_ids(&self) -> Vec<&ContainerId> {
+        let mut ids = vec![&self.container_id];
+        for id in self.services.values() {
+            if !ids.contains(&id) {
+                ids.push(id);
+            }
+        }
+        ids
+    }
+
+    // 環境に属するすべてのコンテナをサービス名とともに返す
+    // サービスを記録する前に作成された環境ではサービス名は分からない
+    pub fn service_containers(&self) -> Vec<(Option<&str>, &ContainerId)> {
+        if self.services.is_empty() {
+            return vec![(None, &self.container_id)];
+        }
+        self.services
+            .iter()
+            .map(|(name, id)| (Some(name.as_str()), id))
+            .collect()
+    }
+}
+
+// ランタイムが管理している、roxyが作成したコンテナ
+#[derive(Debug, Clone)]
+pub struct RuntimeContainer {
+    // コンテナを作成した環境のuuid
+    pub uuid: Uuid,
+    pub container_id: ContainerId,
+    pub runtime: RuntimeKind,
+}
+
+// 環境が作成された日時と最後に入った日時 (ストアが記録する、UTCの"YYYY-MM-DD HH:MM:SS")
+#[derive(Debug, Clone, Default)]
+pub struct EnvTimestamps {
+    pub created_at: Option<String>,
+    pub last_entered_at: Option<String>,
 }
 
 #[derive(Debug, Clone)]
 pub struct EnvRecord {
     pub spec: EnvSpec,
     pub container_info: ContainerInfo,
+    pub timestamps: EnvTimestamps,
 }
 
 #[derive(Tabled)]
@@ -133,19 +483,187 @@ pub struct EnvRecordForList {
     pub uuid: Uuid,
     pub path: String,
     pub name: String,
+    pub template: String,
+    pub runtime: RuntimeKind,
+    pub state: EnvState,
+    // コンテナを調べられなかった場合はunknown
+    pub status: String,
+    // 以下は--longを指定した場合にのみ表示する
+    pub security: SecurityProfile,
+    // ネットワークを指定せずテンプレートの設定を使う場合はtemplate
+    pub network: String,
+    pub created_at: String,
+    pub last_entered_at: String,
+    pub template_hash: String,
+    pub image_id: String,
 }
 
+// EnvRecordForListのうち--longを指定した場合にのみ表示する列
+pub const LONG_LIST_COLUMNS: &[&str] = &[
+    "security",
+    "network",
+    "created_at",
+    "last_entered_at",
+    "template_hash",
+    "image_id",
+];
+
 impl EnvRecordForList {
-    pub fn from_record(record: &EnvRecord) -> Self {
+    pub fn from_record(record: &EnvRecord, status: Option<ContainerStatus>) -> Self {
         Self {
             uuid: record.spec.uuid,
             name: record.spec.project_name.clone(),
             path: record.spec.project_path.display().to_string(),
+            template: record
+                .spec
+                .config
+                .template
+                .clone()
+                .unwrap_or_else(|| DEFAULT_TEMPLATE.to_string()),
+            runtime: record.container_info.runtime,
+            state: record.container_info.state,
+            status: status.map_or_else(|| "unknown".into(), |s| s.to_string()),
+            security: record.spec.config.security.unwrap_or_default(),
+            network: record
+                .spec
+                .config
+                .network
+                .map_or_else(|| "template".into(), |mode| mode.to_string()),
+            created_at: or_dash(record.timestamps.created_at.as_deref()),
+            last_entered_at: or_dash(record.timestamps.last_entered_at.as_deref()),
+            template_hash: or_dash(record.spec.template_hash.as_deref().map(short_hash)),
+            image_id: or_dash(record.container_info.image_id.as_deref().map(short_hash)),
+        }
+    }
+}
+
+// --formatで表以外を指定した場合に出力する環境の情報
+// スクリプトから使われるので、フィールドは追加のみ行い名前や意味は変えない
+#[derive(Serialize)]
+pub struct EnvRecordForOutput {
+    pub uuid: Uuid,
+    pub name: String,
+    pub path: PathBuf,
+    pub template: String,
+    pub template_hash: Option<String>,
+    pub runtime: RuntimeKind,
+    pub state: EnvState,
+    // コンテナを調べられなかった場合はnull
+    pub status: Option<ContainerStatus>,
+    pub container_id: ContainerId,
+    pub services: IndexMap<String, ContainerId>,
+    pub image_id: Option<String>,
+    pub created_at: Option<String>,
+    pub last_entered_at: Option<String>,
+    pub config: ProjectConfig,
+    pub security: SecurityProfile,
+    // ネットワークを指定せずテンプレートの設定を使う場合はnull
+    pub network: Option<NetworkMode>,
+    pub ports: Vec<PortMapping>,
+}
+
+impl EnvRecordForOutput {
+    pub fn from_record(record: &EnvRecord, status: Option<ContainerStatus>) -> Self {
+        Self {
+            uuid: record.spec.uuid,
+            name: record.spec.project_name.clone(),
+            path: record.spec.project_path.clone(),
+            template: record
+                .spec
+                .config
+                .template
+                .clone()
+                .unwrap_or_else(|| DEFAULT_TEMPLATE.to_string()),
+            template_hash: record.spec.template_hash.clone(),
+            runtime: record.container_info.runtime,
+            state: record.container_info.state,
+            status,
+            container_id: record.container_info.container_id.clone(),
+            services: record.container_info.services.clone(),
+            image_id: record.container_info.image_id.clone(),
+            created_at: record.timestamps.created_at.clone(),
+            last_entered_at: record.timestamps.last_entered_at.clone(),
+            config: record.spec.config.clone(),
+            security: record.spec.config.security.unwrap_or_default(),
+            network: record.spec.config.network,
+            ports: record.container_info.ports.clone(),
+        }
+    }
+}
+
+// 環境が一意に定まらない場合に表示する候補
+#[derive(Tabled)]
+pub struct EnvRecordForPicker {
+    pub name: String,
+    pub path: String,
+    // コンテナを調べられなかった場合はunknown
+    pub status: String,
+    // 最後に入ってから (入ったことがなければ作成されてから) の時間
+    pub age: String,
+}
+
+impl EnvRecordForPicker {
+    pub fn from_record(record: &EnvRecord, status: Option<ContainerStatus>) -> Self {
+        let used_at = record
+            .timestamps
+            .last_entered_at
+            .as_deref()
+            .or(record.timestamps.created_at.as_deref());
+        Self {
+            name: record.spec.project_name.clone(),
+            path: record.spec.project_path.display().to_string(),
+            status: status.map_or_else(|| "unknown".into(), |s| s.to_string()),
+            age: or_dash(used_at.and_then(seconds_since).map(format_age).as_deref()),
         }
     }
 }
 
+// 経過時間を最も大きな単位で表す (例: 3d ago)
+fn format_age(seconds: u64) -> String {
+    match seconds {
+        0..60 => "just now".into(),
+        60..3600 => format!("{}m ago", seconds / 60),
+        3600..86400 => format!("{}h ago", seconds / 3600),
+        _ => format!("{}d ago", seconds / 86400),
+    }
+}
+
+// gcで見つかった、どの環境にも属していないもの
+#[derive(Tabled, Serialize)]
+pub struct OrphanForGc {
+    pub kind: &'static str,
+    pub uuid: Uuid,
+    pub target: String,
+}
+
+// 値がない列は"-"と表示する
+fn or_dash(value: Option<&str>) -> String {
+    value.unwrap_or("-").to_string()
+}
+
+fn display_or_dash(value: &Option<String>) -> String {
+    or_dash(value.as_deref())
+}
+
+// ハッシュやイメージidを先頭12文字に縮める ("sha256:"は取り除く)
+fn short_hash(hash: &str) -> &str {
+    let hash = hash.strip_prefix("sha256:").unwrap_or(hash);
+    &hash[..hash.len().min(12)]
+}
+
+#[derive(Tabled, Serialize)]
+pub struct ContainerForStatus {
+    pub name: String,
+    // サービスを記録する前に作成された環境ではサービス名は分からない
+    #[tabled(display = "display_or_dash")]
+    pub service: Option<String>,
+    pub container_id: ContainerId,
+    pub state: EnvState,
+    pub status: String,
+}
+
 pub trait EnvStore {
+    // 他の環境と同じ名前の場合はEnvConflict
     fn insert(&mut self, record: &EnvRecord) -> Result<(), Error>;
 
     fn list(&mut self) -> Result<Vec<EnvRecord>, Error>;
@@ -153,13 +671,30 @@ pub trait EnvStore {
     fn find_by_path(&mut self, path: &Path) -> Result<Vec<EnvRecord>, Error>;
 
     fn find_by_name(&mut self, name: String) -> Result<Vec<EnvRecord>, Error>;
+    // uuid以外の環境がnameを使っていればEnvConflict (pathはエラーの表示に使う)
+    fn ensure_name_available(&mut self, name: &str, uuid: Uuid, path: &Path) -> Result<(), Error>;
     // uuidは仮想環境を一意に定めるか、対応する仮想環境が存在しない
     fn find_by_uuid(&mut self, uuid: Uuid) -> Result<Vec<EnvRecord>, Error>;
 
-    // pathと一致する行をすべて削除する
-    fn remove_by_path(&mut self, path: &Path) -> Result<usize, Error>;
-    // nameと一致する行をすべて削除する
-    fn remove_by_name(&mut self, name: String) -> Result<usize, Error>;
+    // uuidと一致する環境のコンテナ情報を置き換える
+    fn update_container(
+        &mut self,
+        uuid: Uuid,
+        container_info: &ContainerInfo,
+    ) -> Result<usize, Error>;
+
+    // uuidと一致する環境のプロジェクトの設定とテンプレートのハッシュを置き換える
+    fn update_spec(&mut self, uuid: Uuid, spec: &EnvSpec) -> Result<usize, Error>;
+
+    // uuidと一致する環境に最後に入った日時を現在時刻にする
+    fn update_entered(&mut self, uuid: Uuid) -> Result<usize, Error>;
+
+    // uuidと一致する環境の名前を変える (他の環境が使っている名前の場合はEnvConflict)
+    fn rename(&mut self, uuid: Uuid, name: &str) -> Result<usize, Error>;
+
+    // uuidと一致する環境の状態を更新する
+    fn update_state(&mut self, uuid: Uuid, state: EnvState) -> Result<usize, Error>;
+
     // uuidと一致する行をすべて削除する
     fn remove_by_uuid(&mut self, uuid: Uuid) -> Result<usize, Error>;
 
@@ -170,14 +705,6 @@ pub trait EnvStore {
             EnvSpecifier::Uuid(uuid) => self.find_by_uuid(uuid),
         }
     }
-
-    fn remove(&mut self, specifier: EnvSpecifier) -> Result<usize, Error> {
-        match specifier {
-            EnvSpecifier::Name(name) => self.remove_by_name(name),
-            EnvSpecifier::Path(path) => self.remove_by_path(&path),
-            EnvSpecifier::Uuid(uuid) => self.remove_by_uuid(uuid),
-        }
-    }
 }
 
 pub trait Runtime {
@@ -186,6 +713,49 @@ pub trait Runtime {
         shared_resources: &SharedResources,
         env_spec: &EnvSpec,
     ) -> Result<ContainerInfo, Error>;
-    fn enter(&mut self, env_record: &EnvRecord) -> Result<(), Error>;
+    // 現在のテンプレートからコンテナを作り直す (EnvSpecは変わらない)
+    fn rebuild(
+        &mut self,
+        shared_resources: &SharedResources,
+        env_record: &EnvRecord,
+    ) -> Result<ContainerInfo, Error>;
+    // serviceが指定されていない場合は主サービスのコンテナに入る
+    // shellが指定されていない場合はテンプレートの設定もしくはコンテナ内で見つかったシェルを使う
+    fn enter(
+        &mut self,
+        env_record: &EnvRecord,
+        service: Option<&str>,
+        shell: Option<&str>,
+    ) -> Result<(), Error>;
+    // 環境内でコマンドを実行し、その終了コードを返す
+    fn exec(
+        &mut self,
+        env_record: &EnvRecord,
+        service: Option<&str>,
+        argv: &[String],
+        tty: bool,
+    ) -> Result<i32, Error>;
+    // 環境のコンテナをすべて、中身を残したまま停止する
+    fn stop(&mut self, env_record: &EnvRecord) -> Result<(), Error>;
+    // 停止したコンテナをすべて再開する
+    fn start(&mut self, env_record: &EnvRecord) -> Result<(), Error>;
     fn kill(&mut self, env_record: &EnvRecord) -> Result<(), Error>;
+    // コンテナの実際の状態を調べる
+    fn inspect(
+        &mut self,
+        env_record: &EnvRecord,
+        container_id: &ContainerId,
+    ) -> Result<ContainerStatus, Error>;
+    // 記録されたコンテナが見つからない場合に、環境のコンテナを探し直す
+    // 見つからなかった場合はNoneを返す
+    fn refresh(&mut self, env_record: &EnvRecord) -> Result<Option<ContainerInfo>, Error>;
+    // 設定ディレクトリのcompose.ymlに書き出された、公開しているポートを返す
+    fn published_ports(&mut self, env_record: &EnvRecord) -> Result<Vec<PortMapping>, Error>;
+    // このランタイムでroxyが作成したコンテナをすべて返す
+    fn containers(&mut self) -> Result<Vec<RuntimeContainer>, Error>;
+    // 環境に属していないコンテナを削除する
+    fn remove_container(&mut self, container: &RuntimeContainer) -> Result<(), Error>;
+    // 設定ディレクトリを環境のuuidとともにすべて返す
+    fn config_dirs(&mut self) -> Result<Vec<(Uuid, PathBuf)>, Error>;
+    fn remove_config_dir(&mut self, uuid: Uuid) -> Result<(), Error>;
 }
@@ -4,7 +4,7 @@ use log::{error, info};
 
 use crate::domain::repo::{EnvSpecifier, EnvStore, Runtime};
 
-use super::specify_env_to_operate;
+use super::{ensure_running, specify_env_to_operate};
 
 pub(crate) struct EnterHandler<R: Runtime, S: EnvStore> {
     runtime: R,
@@ -16,12 +16,38 @@ impl<R: Runtime, S: EnvStore> EnterHandler<R, S> {
         Self { runtime, env_store }
     }
 
-    pub fn handle(&mut self, current_path: &Path, env_specifier: Option<EnvSpecifier>) {
-        if let Some(env_record) =
-            specify_env_to_operate(&mut self.env_store, current_path, env_specifier)
-        {
+    pub fn handle(
+        &mut self,
+        current_path: &Path,
+        env_specifier: Option<EnvSpecifier>,
+        service: Option<&str>,
+        shell: Option<&str>,
+        interactive: bool,
+    ) {
+        if let Some(env_record) = specify_env_to_operate(
+            &mut self.runtime,
+            &mut self.env_store,
+            current_path,
+            env_specifier,
+            interactive,
+        ) {
+            // 停止している場合は起動してから入る
+            if !ensure_running(&mut self.runtime, &mut self.env_store, &env_record) {
+                return;
+            }
+
+            // プロジェクトの設定のシェルは主サービスに入る場合にのみ使う
+            let shell = match service {
+                Some(_) => shell,
+                None => shell.or(env_record.spec.config.shell.as_deref()),
+            };
+
+            if let Err(err) = self.env_store.update_entered(env_record.spec.uuid) {
+                error!("Failed to update the environment record: {err}");
+            }
+
             info!("entering to {}", env_record.spec.project_name);
-            if let Err(err) = self.runtime.enter(&env_record) {
+            if let Err(err) = self.runtime.enter(&env_record, service, shell) {
                 error!("failed to enter the environment: {err}");
             }
         }
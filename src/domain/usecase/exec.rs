@@ -0,0 +1,52 @@
+use std::path::Path;
+
+use log::error;
+
+use crate::domain::repo::{EnvSpecifier, EnvStore, Runtime};
+
+use super::{ensure_running, specify_env_to_operate};
+
+pub(crate) struct ExecHandler<R: Runtime, S: EnvStore> {
+    runtime: R,
+    env_store: S,
+}
+
+impl<R: Runtime, S: EnvStore> ExecHandler<R, S> {
+    pub fn new(runtime: R, env_store: S) -> Self {
+        Self { runtime, env_store }
+    }
+
+    // コマンドの終了コードを返す
+    pub fn handle(
+        &mut self,
+        current_path: &Path,
+        env_specifier: Option<EnvSpecifier>,
+        service: Option<&str>,
+        argv: &[String],
+        tty: bool,
+        interactive: bool,
+    ) -> i32 {
+        let Some(env_record) = specify_env_to_operate(
+            &mut self.runtime,
+            &mut self.env_store,
+            current_path,
+            env_specifier,
+            interactive,
+        ) else {
+            return 1;
+        };
+
+        // 停止している場合は起動してから実行する
+        if !ensure_running(&mut self.runtime, &mut self.env_store, &env_record) {
+            return 1;
+        }
+
+        match self.runtime.exec(&env_record, service, argv, tty) {
+            Ok(code) => code,
+            Err(err) => {
+                error!("Failed to execute the command in the environment: {err}");
+                1
+            }
+        }
+    }
+}
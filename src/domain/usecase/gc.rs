@@ -0,0 +1,310 @@
+use std::collections::HashMap;
+
+use log::{error, info};
+use tabled::Table;
+use tabled::settings::Style;
+
+use crate::domain::repo::{
+    ContainerInfo, ContainerStatus, EnvRecord, EnvStore, OrphanForGc, Runtime, SharedResources,
+};
+
+use super::output::{OutputFormat, print_rows};
+use super::{env_status, lock_envs};
+
+pub(crate) struct GcHandler<R: Runtime, S: EnvStore> {
+    runtime: R,
+    env_store: S,
+}
+
+impl<R: Runtime, S: EnvStore> GcHandler<R, S> {
+    pub fn new(runtime: R, env_store: S) -> Self {
+        Self { runtime, env_store }
+    }
+
+    // 記録、コンテナ、設定ディレクトリを突き合わせ、どの環境にも属さないものを削除する
+    // dry_runの場合は表示するだけで削除しない
+    pub fn handle(
+        &mut self,
+        shared_resources: &SharedResources,
+        dry_run: bool,
+        format: OutputFormat,
+    ) {
+        // 作成途中の環境を残骸とみなさないように、環境を作成するコマンドと排他する
+        let Some(_lock) = lock_envs(shared_resources) else {
+            return;
+        };
+
+        let env_records = match self.env_store.list() {
+            Ok(v) => v,
+            Err(err) => {
+                error!("Failed to get list of environments: {err:?}");
+                return;
+            }
+        };
+
+        // コンテナがすべて失われ、探し直しても見つからない環境の記録
+        // 生きている環境は実際のコンテナのid (調べられなかった場合はNone) とともに残す
+        let mut dead_records = Vec::new();
+        let mut live_records = HashMap::new();
+        for env_record in env_records {
+            match self.liveness(&env_record) {
+                Liveness::Dead => dead_records.push(env_record),
+                Liveness::Live(container_info) => {
+                    live_records.insert(env_record.spec.uuid, Some(container_info));
+                }
+                Liveness::Unknown => {
+                    live_records.insert(env_record.spec.uuid, None);
+                }
+            }
+        }
+
+        // 生きている環境に属さないコンテナ (失敗したinitやrebuildの残骸)
+        // 記録されたidが古い環境は探し直したidと比べ、調べられなかった環境のコンテナは残す
+        let containers = match self.runtime.containers() {
+            Ok(v) => v,
+            Err(err) => {
+                error!("Failed to list containers: {err}");
+                return;
+            }
+        };
+        let orphan_containers = containers
+            .into_iter()
+            .filter(|container| match live_records.get(&container.uuid) {
+                Some(Some(container_info)) => !container_info
+                    .container_ids()
+                    .iter()
+                    .any(|id| id.is_same(&container.container_id)),
+                Some(None) => false,
+                None => true,
+            })
+            .collect::<Vec<_>>();
+
+        // 生きている環境に属さない設定ディレクトリ
+        let config_dirs = match self.runtime.config_dirs() {
+            Ok(v) => v,
+            Err(err) => {
+                error!("Failed to list config directories: {err}");
+                return;
+            }
+        };
+        let orphan_config_dirs = config_dirs
+            .into_iter()
+            .filter(|(uuid, _)| !live_records.contains_key(uuid))
+            .collect::<Vec<_>>();
+
+        // 見つかったものを出力する (表以外の形式では何も見つからなくても空の一覧を出力する)
+        let mut orphans = Vec::new();
+        orphans.extend(dead_records.iter().map(|record| OrphanForGc {
+            kind: "record",
+            uuid: record.spec.uuid,
+            target: record.spec.project_name.clone(),
+        }));
+        orphans.extend(orphan_containers.iter().map(|container| OrphanForGc {
+            kind: "container",
+            uuid: container.uuid,
+            target: format!("{} ({})", container.container_id, container.runtime),
+        }));
+        orphans.extend(orphan_config_dirs.iter().map(|(uuid, path)| OrphanForGc {
+            kind: "config dir",
+            uuid: *uuid,
+            target: path.display().to_string(),
+        }));
+
+        if orphans.is_empty() {
+            info!("Nothing to collect.");
+            if format != OutputFormat::Table {
+                print_rows(format, Table::new(&orphans), &orphans);
+            }
+            return;
+        }
+
+        let mut table = Table::new(&orphans);
+        table.with(Style::blank());
+
+        print_rows(format, table, &orphans);
+
+        if dry_run {
+            info!("Dry run: nothing was removed.");
+            return;
+        }
+
+        // 削除する (失敗しても残りの削除は続ける)
+        for record in &dead_records {
+            info!("Removing the record of {}", record.spec.project_name);
+            if let Err(err) = self.env_store.remove_by_uuid(record.spec.uuid) {
+                error!("Failed to remove the environment record: {err}");
+            }
+        }
+        for container in &orphan_containers {
+            info!("Removing container {}", container.container_id);
+            if let Err(err) = self.runtime.remove_container(container) {
+                error!("Failed to remove the container: {err}");
+            }
+        }
+        for (uuid, path) in &orphan_config_dirs {
+            info!("Removing {}", path.display());
+            if let Err(err) = self.runtime.remove_config_dir(*uuid) {
+                error!("Failed to remove the config directory: {err}");
+            }
+        }
+    }
+
+    // 環境のコンテナがすべて失われているかどうかを調べる
+    // 記録されたidが古いだけの場合はstatus --fixで直せるので削除せず、探し直したコンテナを返す
+    fn liveness(&mut self, env_record: &EnvRecord) -> Liveness {
+        match env_status(&mut self.runtime, env_record) {
+            Ok(ContainerStatus::Missing) => {}
+            Ok(_) => return Liveness::Live(env_record.container_info.clone()),
+            Err(err) => {
+                error!("Failed to inspect {}: {err}", env_record.spec.project_name);
+                return Liveness::Unknown;
+            }
+        }
+
+        match self.runtime.refresh(env_record) {
+            Ok(Some(container_info)) => Liveness::Live(container_info),
+            Ok(None) => Liveness::Dead,
+            Err(err) => {
+                error!(
+                    "Failed to look up the containers of {}: {err}",
+                    env_record.spec.project_name
+                );
+                Liveness::Unknown
+            }
+        }
+    }
+}
+
+// 記録された環境のコンテナの状態
+enum Liveness {
+    // コンテナがすべて失われ、探し直しても見つからない
+    Dead,
+    // 環境のコンテナ (記録されたidが古い場合は探し直したもの)
+    Live(ContainerInfo),
+    // 調べられなかった (生きているとみなし、コンテナも削除しない)
+    Unknown,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::path::{Path, PathBuf};
+
+    use indexmap::IndexMap;
+    use uuid::Uuid;
+
+    use crate::domain::repo::{
+        ContainerId, EnvSpec, Error, PortMapping, RuntimeContainer, RuntimeKind,
+    };
+    use crate::infra::sqlite::SqliteForContainerStore;
+    use crate::infra::sqlite::tests::{TempDb, record};
+
+    use super::*;
+
+    // 記録されたidのコンテナは失われているが、ラベルからは実際のコンテナが見つかるランタイム
+    struct StaleIdRuntime {
+        uuid: Uuid,
+        actual: ContainerId,
+        removed: Vec<ContainerId>,
+    }
+
+    impl Runtime for StaleIdRuntime {
+        fn init(&mut self, _: &SharedResources, _: &EnvSpec) -> Result<ContainerInfo, Error> {
+            unimplemented!()
+        }
+        fn rebuild(&mut self, _: &SharedResources, _: &EnvRecord) -> Result<ContainerInfo, Error> {
+            unimplemented!()
+        }
+        fn enter(&mut self, _: &EnvRecord, _: Option<&str>, _: Option<&str>) -> Result<(), Error> {
+            unimplemented!()
+        }
+        fn exec(
+            &mut self,
+            _: &EnvRecord,
+            _: Option<&str>,
+            _: &[String],
+            _: bool,
+        ) -> Result<i32, Error> {
+            unimplemented!()
+        }
+        fn stop(&mut self, _: &EnvRecord) -> Result<(), Error> {
+            unimplemented!()
+        }
+        fn start(&mut self, _: &EnvRecord) -> Result<(), Error> {
+            unimplemented!()
+        }
+        fn kill(&mut self, _: &EnvRecord) -> Result<(), Error> {
+            unimplemented!()
+        }
+        fn inspect(
+            &mut self,
+            _: &EnvRecord,
+            container_id: &ContainerId,
+        ) -> Result<ContainerStatus, Error> {
+            if container_id.is_same(&self.actual) {
+                Ok(ContainerStatus::Running)
+            } else {
+                Ok(ContainerStatus::Missing)
+            }
+        }
+        fn refresh(&mut self, env_record: &EnvRecord) -> Result<Option<ContainerInfo>, Error> {
+            Ok(Some(ContainerInfo {
+                container_id: self.actual.clone(),
+                services: IndexMap::from([("app".to_string(), self.actual.clone())]),
+                ..env_record.container_info.clone()
+            }))
+        }
+        fn published_ports(&mut self, _: &EnvRecord) -> Result<Vec<PortMapping>, Error> {
+            unimplemented!()
+        }
+        fn containers(&mut self) -> Result<Vec<RuntimeContainer>, Error> {
+            Ok(vec![RuntimeContainer {
+                uuid: self.uuid,
+                container_id: self.actual.clone(),
+                runtime: RuntimeKind::DockerApi,
+            }])
+        }
+        fn remove_container(&mut self, container: &RuntimeContainer) -> Result<(), Error> {
+            self.removed.push(container.container_id.clone());
+            Ok(())
+        }
+        fn config_dirs(&mut self) -> Result<Vec<(Uuid, PathBuf)>, Error> {
+            Ok(Vec::new())
+        }
+        fn remove_config_dir(&mut self, _: Uuid) -> Result<(), Error> {
+            unimplemented!()
+        }
+    }
+
+    #[test]
+    fn keeps_containers_of_env_with_stale_ids() {
+        let db = TempDb::new();
+        let shared_dir = env::temp_dir().join(format!("roxy-gc-{}", Uuid::new_v4()));
+        let shared_resources = SharedResources::new(shared_dir.clone());
+
+        let mut store = SqliteForContainerStore::new(&db.0).unwrap();
+        let env_record = record(Path::new("/tmp/project"), "project");
+        store.insert(&env_record).unwrap();
+
+        let runtime = StaleIdRuntime {
+            uuid: env_record.spec.uuid,
+            actual: ContainerId::from_str("actual"),
+            removed: Vec::new(),
+        };
+        let mut handler = GcHandler::new(runtime, store);
+        handler.handle(&shared_resources, false, OutputFormat::Json);
+
+        assert!(handler.runtime.removed.is_empty());
+        assert_eq!(
+            handler
+                .env_store
+                .find_by_uuid(env_record.spec.uuid)
+                .unwrap()
+                .len(),
+            1
+        );
+
+        let _ = fs::remove_dir_all(&shared_dir);
+    }
+}
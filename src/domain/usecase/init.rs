@@ -1,11 +1,16 @@
 use std::path::Path;
 
-use log::error;
+use log::{error, info};
 use uuid::Uuid;
 
-use crate::domain::repo::{EnvRecord, EnvSpec, EnvStore, Runtime, SharedResources};
+use crate::domain::config::{NetworkMode, ProjectConfig, SecurityProfile};
+use crate::domain::repo::{
+    EnvRecord, EnvSpec, EnvStore, EnvTimestamps, Error, Runtime, SharedResources,
+};
 use crate::util::get_entry_name;
 
+use super::{is_valid_name, lock_envs};
+
 pub(crate) struct InitHandler<R: Runtime, S: EnvStore> {
     runtime: R,
     env_store: S,
@@ -16,7 +21,15 @@ impl<R: Runtime, S: EnvStore> InitHandler<R, S> {
         Self { runtime, env_store }
     }
 
-    pub fn handle(&mut self, project_path: &Path, shared_resources: &SharedResources) {
+    pub fn handle(
+        &mut self,
+        project_path: &Path,
+        shared_resources: &SharedResources,
+        template: Option<String>,
+        security: Option<SecurityProfile>,
+        network: Option<NetworkMode>,
+        name: Option<String>,
+    ) {
         // 指定されたディレクトリに紐づいた環境が存在するか確認する
         let env_record = match self.env_store.find_by_path(project_path) {
             Ok(o) => o,
@@ -35,18 +48,80 @@ impl<R: Runtime, S: EnvStore> InitHandler<R, S> {
             return;
         }
 
-        // EnvSpecを構築する
-        let project_name = get_entry_name(project_path);
+        // プロジェクトの設定を読み込む
+        let mut config = match ProjectConfig::load(project_path) {
+            Ok(c) => c,
+            Err(err) => {
+                error!("Failed to load the project configuration: {err}");
+                return;
+            }
+        };
+
+        // コマンドラインで指定されたテンプレートは設定ファイルより優先する
+        if template.is_some() {
+            config.template = template;
+        }
+        if security.is_some() {
+            config.security = security;
+        }
+        // 適用するセキュリティプロファイルを記録し、一覧などで表示できるようにする
+        config.security.get_or_insert_default();
+        if network.is_some() {
+            config.network = network;
+        }
+
+        // 使用するテンプレートの内容を記録しておく (テンプレートが変更されたか分かるように)
+        let template_hash = match shared_resources.template_hash(config.template.as_deref()) {
+            Ok(h) => h,
+            Err(err) => {
+                error!("Failed to read the template: {err}");
+                return;
+            }
+        };
 
         let id = Uuid::new_v4();
 
+        // 環境の名前を決める (名前は他の環境と重複してはならない)
+        // コンテナを作成してから重複が分からないように、作成する前にストアに確かめる
+        let project_name = match name {
+            Some(name) => {
+                if !is_valid_name(&name) {
+                    error!("Invalid environment name: {name:?}");
+                    return;
+                }
+                if let Err(err) = self
+                    .env_store
+                    .ensure_name_available(&name, id, project_path)
+                {
+                    error!("{err}");
+                    return;
+                }
+                name
+            }
+            // 指定がない場合はディレクトリ名を使い、既に使われていれば番号を付ける
+            None => match self.available_name(&get_entry_name(project_path)) {
+                Ok(name) => name,
+                Err(err) => {
+                    error!("Failed to find the environment by name: {err}");
+                    return;
+                }
+            },
+        };
+
+        // EnvSpecを構築する
+
         let env_spec = EnvSpec {
             uuid: id,
             project_path: project_path.to_path_buf(),
             project_name,
+            config,
+            template_hash: Some(template_hash),
         };
 
-        // 環境を立ち上げる
+        // 環境を立ち上げる (記録を保存するまでgcや他のinitと排他する)
+        let Some(lock) = lock_envs(shared_resources) else {
+            return;
+        };
         let container_info = match self.runtime.init(shared_resources, &env_spec) {
             Ok(i) => i,
             Err(err) => {
@@ -60,16 +135,44 @@ impl<R: Runtime, S: EnvStore> InitHandler<R, S> {
         let env_record = EnvRecord {
             container_info,
             spec: env_spec,
+            timestamps: EnvTimestamps::default(),
         };
 
         // EnvRecordを保存する
-        if self.env_store.insert(&env_record).is_err() {
-            error!("Failed to store environment record.");
+        // 確かめたあとに他のinitが同じ名前を使った場合などは、記録のない環境が残らないように削除する
+        if let Err(err) = self.env_store.insert(&env_record) {
+            error!("Failed to store environment record: {err}");
+            if let Err(err) = self.runtime.kill(&env_record) {
+                error!("Failed to remove the environment: {err}");
+            }
+            return;
+        }
+        drop(lock);
+        if let Err(err) = self.env_store.update_entered(env_record.spec.uuid) {
+            error!("Failed to update the environment record: {err}");
         }
 
         // 環境に入る
-        if let Err(err) = self.runtime.enter(&env_record) {
-            error!("Failed to enter to the environment.");
+        let shell = env_record.spec.config.shell.clone();
+        if let Err(err) = self.runtime.enter(&env_record, None, shell.as_deref()) {
+            error!("Failed to enter to the environment: {err}");
+        }
+    }
+
+    // baseが使われていなければそのまま、使われていれば"base-2"から順に空いている名前を返す
+    fn available_name(&mut self, base: &str) -> Result<String, Error> {
+        if self.env_store.find_by_name(base.to_string())?.is_empty() {
+            return Ok(base.to_string());
+        }
+
+        let mut n = 2u64;
+        loop {
+            let name = format!("{base}-{n}");
+            if self.env_store.find_by_name(name.clone())?.is_empty() {
+                info!("{base} is already used; naming the environment {name}.");
+                return Ok(name);
+            }
+            n += 1;
         }
     }
 }
@@ -1,6 +1,5 @@
 use std::path::Path;
 
-use clap::Parser;
 use log::{error, info};
 
 use crate::domain::repo::{EnvSpecifier, EnvStore, Runtime};
@@ -17,10 +16,19 @@ impl<R: Runtime, S: EnvStore> KillHandler<R, S> {
         Self { runtime, env_store }
     }
 
-    pub fn handle(&mut self, current_path: &Path, env_specifier: Option<EnvSpecifier>) {
-        if let Some(env_record) =
-            specify_env_to_operate(&mut self.env_store, current_path, env_specifier)
-        {
+    pub fn handle(
+        &mut self,
+        current_path: &Path,
+        env_specifier: Option<EnvSpecifier>,
+        interactive: bool,
+    ) {
+        if let Some(env_record) = specify_env_to_operate(
+            &mut self.runtime,
+            &mut self.env_store,
+            current_path,
+            env_specifier,
+            interactive,
+        ) {
             let records = match self.env_store.find_by_uuid(env_record.spec.uuid) {
                 Ok(o) => o,
                 Err(err) => {
@@ -42,7 +50,9 @@ impl<R: Runtime, S: EnvStore> KillHandler<R, S> {
 
             // 環境を終了する
             if let Err(err) = self.runtime.kill(&env_record) {
+                // 残ったコンテナを後から削除できるように環境の情報は残す
                 error!("Failed to kill the environment: {err}");
+                return;
             }
 
             // 環境の情報を破棄する
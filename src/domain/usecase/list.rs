@@ -1,8 +1,14 @@
-use log::error;
+use log::{error, warn};
 use tabled::Table;
-use tabled::settings::Style;
+use tabled::settings::location::ByColumnName;
+use tabled::settings::{Remove, Style};
 
-use crate::domain::repo::{EnvRecordForList, EnvStore, Runtime};
+use crate::domain::repo::{
+    EnvRecordForList, EnvRecordForOutput, EnvStore, LONG_LIST_COLUMNS, Runtime,
+};
+
+use super::env_status;
+use super::output::{OutputFormat, print_rows};
 
 pub(crate) struct ListHandler<R: Runtime, S: EnvStore> {
     runtime: R,
@@ -14,7 +20,9 @@ impl<R: Runtime, S: EnvStore> ListHandler<R, S> {
         Self { runtime, env_store }
     }
 
-    pub fn handle(&mut self) {
+    // longが指定された場合は作成日時などの列も表示する
+    // 表以外の形式では常にすべての情報を出力する
+    pub fn handle(&mut self, long: bool, format: OutputFormat) {
         // 現在存在するすべての環境の一覧を取得する
         let env_records = match self.env_store.list() {
             Ok(v) => v,
@@ -24,15 +32,37 @@ impl<R: Runtime, S: EnvStore> ListHandler<R, S> {
             }
         };
 
-        // 表として出力する
+        // コンテナの実際の状態とともに出力する
+        let statuses = env_records
+            .iter()
+            .map(|record| match env_status(&mut self.runtime, record) {
+                Ok(status) => Some(status),
+                Err(err) => {
+                    warn!("Failed to inspect {}: {err}", record.spec.project_name);
+                    None
+                }
+            })
+            .collect::<Vec<_>>();
+
         let env_records_for_list = env_records
             .iter()
-            .map(EnvRecordForList::from_record)
+            .zip(&statuses)
+            .map(|(record, status)| EnvRecordForList::from_record(record, *status))
             .collect::<Vec<EnvRecordForList>>();
+        let env_records_for_output = env_records
+            .iter()
+            .zip(&statuses)
+            .map(|(record, status)| EnvRecordForOutput::from_record(record, *status))
+            .collect::<Vec<EnvRecordForOutput>>();
 
         let mut table = Table::new(env_records_for_list);
         table.with(Style::blank());
+        if !long {
+            for column in LONG_LIST_COLUMNS {
+                table.with(Remove::column(ByColumnName::new(*column)));
+            }
+        }
 
-        println!("{table}");
+        print_rows(format, table, &env_records_for_output);
     }
 }
@@ -1,34 +1,113 @@
 mod enter;
+mod exec;
+mod gc;
 mod init;
 mod kill;
 mod list;
+mod output;
+mod port;
+mod rebuild;
+mod rename;
+mod start;
+mod status;
+mod stop;
+mod template;
 
-use std::path::Path;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
 
 use log::{error, info};
+use tabled::Table;
+use tabled::settings::object::Rows;
+use tabled::settings::{Remove, Style};
 
-use crate::infra::docker::DockerForContainerRuntime;
+use crate::infra::dispatch::{RuntimeDispatcher, detect_runtime};
+use crate::infra::picker;
 use crate::infra::sqlite::SqliteForContainerStore;
+use crate::util::canonicalize;
+use crate::util::lock::FileLock;
 
 use self::enter::EnterHandler;
+use self::exec::ExecHandler;
+use self::gc::GcHandler;
 use self::init::InitHandler;
 use self::kill::KillHandler;
 use self::list::ListHandler;
+pub use self::output::OutputFormat;
+use self::port::PortHandler;
+use self::rebuild::RebuildHandler;
+use self::rename::RenameHandler;
+use self::start::StartHandler;
+use self::status::StatusHandler;
+use self::stop::StopHandler;
+use self::template::TemplateHandler;
 
-use super::repo::{EnvRecord, EnvSpecifier, EnvStore, SharedResources};
+use super::config::{NetworkMode, SecurityProfile};
+use super::repo::{
+    ContainerStatus, EnvRecord, EnvRecordForPicker, EnvSpecifier, EnvState, EnvStore, Error,
+    Runtime, RuntimeKind, SharedResources,
+};
 
 pub enum Action {
-    Init,
-    List,
-    Enter(Option<EnvSpecifier>),
+    Init {
+        // 指定がない場合はカレントディレクトリ
+        path: Option<PathBuf>,
+        template: Option<String>,
+        security: Option<SecurityProfile>,
+        network: Option<NetworkMode>,
+        name: Option<String>,
+    },
+    List {
+        long: bool,
+    },
+    Enter {
+        specifier: Option<EnvSpecifier>,
+        service: Option<String>,
+        shell: Option<String>,
+    },
     Kill(Option<EnvSpecifier>),
+    Stop(Option<EnvSpecifier>),
+    Start(Option<EnvSpecifier>),
+    Rebuild(Option<EnvSpecifier>),
+    Status {
+        fix: bool,
+    },
+    Gc {
+        dry_run: bool,
+    },
+    TemplateList,
+    Rename {
+        specifier: Option<EnvSpecifier>,
+        name: String,
+    },
+    Port(Option<EnvSpecifier>),
+    Exec {
+        specifier: Option<EnvSpecifier>,
+        service: Option<String>,
+        argv: Vec<String>,
+        tty: bool,
+    },
+}
+
+// すべてのアクションに共通するオプション
+pub struct Options {
+    // 新しい環境を作成するランタイム (指定がない場合は自動で検出する)
+    pub runtime: Option<RuntimeKind>,
+    // 環境の情報などを出力する形式
+    pub format: OutputFormat,
+    // 環境が一意に定まらない場合に候補から選ばせるか (端末から実行され、--no-inputがない場合)
+    pub interactive: bool,
 }
 
 // カレントディレクトリと環境指定子から最終的にどの環境を選択するのかを返す関数
-fn specify_env_to_operate<E: EnvStore>(
+// 環境が一意に定まらない場合、interactiveなら候補から選ばせる
+fn specify_env_to_operate<R: Runtime, E: EnvStore>(
+    runtime: &mut R,
     env_store: &mut E,
     current_path: &Path,
     env_specifier: Option<EnvSpecifier>,
+    interactive: bool,
 ) -> Option<EnvRecord> {
     // 環境が指定されているか確認する
     if let Some(specifier) = env_specifier {
@@ -54,8 +133,16 @@ fn specify_env_to_operate<E: EnvStore>(
             return Some(records[0].clone());
         }
 
-        // 環境が一意に定まらなかった場合はエラーを出して終了する
-        error!("The environment wasn't uniquely determined.");
+        // 環境が一意に定まらなかった場合は候補から選ばせる
+        if interactive {
+            return pick_env(runtime, records);
+        }
+
+        // 選ばせられない場合はエラーを出して終了する
+        error!(
+            "The environment wasn't uniquely determined ({} candidates). Specify it by uuid.",
+            records.len()
+        );
         return None;
     }
 
@@ -82,8 +169,8 @@ fn specify_env_to_operate<E: EnvStore>(
         return Some(env_records[0].clone());
     }
 
-    // カレントディレクトリに紐づいた環境が存在するか確認する
-    let env_records = match env_store.find_by_path(current_path) {
+    // カレントディレクトリから親をたどり、最も近いディレクトリに紐づいた環境を探す
+    let linked_records = match find_by_ancestors(env_store, current_path) {
         Ok(o) => o,
         Err(err) => {
             error!("Failed to find environment: {err:?}");
@@ -91,49 +178,324 @@ fn specify_env_to_operate<E: EnvStore>(
         }
     };
 
-    if env_records.is_empty() {
-        // カレントディレクトリに紐づいた環境が存在しない場合
-        // 指定子なしで選択できる環境が存在しない旨を伝える
-        info!("There is no environment that can be selected without a specifier.");
+    if linked_records.is_empty() {
+        // カレントディレクトリとその親に紐づいた環境が存在しない場合はすべての環境から選ばせる
+        if interactive {
+            return pick_env(runtime, env_records);
+        }
+
+        // 選ばせられない場合は指定子なしで選択できる環境が存在しない旨を伝える
+        error!(
+            "There is no environment that can be selected without a specifier. \
+             Specify it by name, path or uuid (see `roxy list`)."
+        );
         return None;
     }
 
-    if env_records.len() == 1 {
-        // カレントディレクトリに紐づいた環境が1つしか存在しないならその環境を選択する
-        return Some(env_records[0].clone());
+    if linked_records.len() == 1 {
+        // 最も近いディレクトリに紐づいた環境が1つしか存在しないならその環境を選択する
+        return Some(linked_records[0].clone());
     }
 
-    // カレントディレクトリに紐づいた環境が複数存在する状態は起きてはならないのでエラー
+    // 同じディレクトリに紐づいた環境が複数存在する状態は起きてはならないのでエラー
     error!("Multiple environments mustn't be linked to the same path.");
-    return None;
+    None
+}
+
+// pathとその親のうち、環境が紐づいた最も近いディレクトリの環境を返す (見つからなければ空)
+fn find_by_ancestors<S: EnvStore>(env_store: &mut S, path: &Path) -> Result<Vec<EnvRecord>, Error> {
+    for ancestor in canonicalize(path).ancestors() {
+        let records = env_store.find_by_path(ancestor)?;
+        if !records.is_empty() {
+            return Ok(records);
+        }
+    }
+    Ok(Vec::new())
+}
+
+// 候補の環境を名前、パス、状態、最後に使われてからの時間とともに表示して選ばせる
+fn pick_env<R: Runtime>(runtime: &mut R, env_records: Vec<EnvRecord>) -> Option<EnvRecord> {
+    let candidates = env_records
+        .iter()
+        .map(|record| {
+            let status = env_status(runtime, record).ok();
+            EnvRecordForPicker::from_record(record, status)
+        })
+        .collect::<Vec<_>>();
+
+    // 列を揃えるために見出しのない表の各行を候補とする
+    let mut table = Table::new(candidates);
+    table.with(Style::blank()).with(Remove::row(Rows::first()));
+    let items = table
+        .to_string()
+        .lines()
+        .map(|line| line.trim().to_string())
+        .collect::<Vec<_>>();
+
+    match picker::pick("Select an environment", &items) {
+        Ok(Some(index)) => env_records.into_iter().nth(index),
+        Ok(None) => {
+            info!("Cancelled.");
+            None
+        }
+        Err(err) => {
+            error!("Failed to select an environment: {err}");
+            None
+        }
+    }
+}
+
+// 環境を作成するプロジェクトのディレクトリを正規化して返す (相対パスはカレントディレクトリが基準)
+fn project_path(current_path: &Path, path: Option<&Path>) -> Result<PathBuf, Error> {
+    let path = current_path.join(path.unwrap_or(Path::new(".")));
+    let canonical = fs::canonicalize(&path).map_err(|err| Error::InvalidPath {
+        path: path.clone(),
+        msg: match err.kind() {
+            io::ErrorKind::NotFound => "doesn't exist".into(),
+            _ => err.to_string(),
+        },
+    })?;
+    if !canonical.is_dir() {
+        return Err(Error::InvalidPath {
+            path,
+            msg: "not a directory".into(),
+        });
+    }
+    Ok(canonical)
+}
+
+// 環境の名前として使えるか (空白だけの名前や前後に空白を含む名前は指定しにくいので使えない)
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty() && name.trim() == name && !name.chars().any(char::is_control)
 }
 
-pub fn handle(action: Action, current_path: &Path, shared_resources: &SharedResources) {
-    let docker = DockerForContainerRuntime::new();
+// 停止している環境を起動する (起動できた、もしくは既に起動している場合はtrueを返す)
+fn ensure_running<R: Runtime, E: EnvStore>(
+    runtime: &mut R,
+    env_store: &mut E,
+    env_record: &EnvRecord,
+) -> bool {
+    if env_record.container_info.state == EnvState::Running {
+        return true;
+    }
+
+    info!("Starting {}", env_record.spec.project_name);
+
+    if let Err(err) = runtime.start(env_record) {
+        error!("Failed to start the environment: {err}");
+        return false;
+    }
+
+    if let Err(err) = env_store.update_state(env_record.spec.uuid, EnvState::Running) {
+        error!("Failed to update the environment record: {err}");
+    }
+
+    true
+}
+
+// 環境を作成するコマンドとgcのあいだで排他する (取得できなかった場合はエラーを出力してNone)
+// 作成途中の環境は記録が保存されるまでどの環境にも属さないように見えるので、gcに消されないようにする
+// また、並行するinitが同じホストのポートを割り当てないようにする
+fn lock_envs(shared_resources: &SharedResources) -> Option<FileLock> {
+    let path = shared_resources.lock_absolute_path();
+    match FileLock::acquire(&path) {
+        Ok(lock) => Some(lock),
+        Err(err) => {
+            error!("Failed to lock {}: {err}", path.display());
+            None
+        }
+    }
+}
+
+// 環境のコンテナをすべて調べ、最も深刻な状態を環境の状態として返す
+fn env_status<R: Runtime>(
+    runtime: &mut R,
+    env_record: &EnvRecord,
+) -> Result<ContainerStatus, Error> {
+    let mut status = ContainerStatus::Running;
+    for container_id in env_record.container_info.container_ids() {
+        status = status.max(runtime.inspect(env_record, container_id)?);
+    }
+    Ok(status)
+}
+
+// シェルの補完候補にする環境の一覧を返す (補完を妨げないよう失敗しても何も出力しない)
+// データベースがない場合や移行されていない場合は候補なしとする
+pub fn env_records_for_completion(shared_resources: &SharedResources) -> Vec<EnvRecord> {
+    SqliteForContainerStore::open_read_only(&shared_resources.database_absolute_path())
+        .and_then(|mut sqlite| sqlite.list())
+        .unwrap_or_default()
+}
+
+// プロセスの終了コードを返す
+pub fn handle(
+    action: Action,
+    current_path: &Path,
+    shared_resources: &SharedResources,
+    options: &Options,
+) -> i32 {
+    let runtime = RuntimeDispatcher::new(options.runtime.unwrap_or_else(detect_runtime));
     let sqlite = match SqliteForContainerStore::new(&shared_resources.database_absolute_path()) {
         Ok(v) => v,
-        Err(_err) => {
-            error!("Failed to create sqlite service.");
-            return;
+        Err(err) => {
+            error!("Failed to create sqlite service: {err}");
+            return 1;
         }
     };
 
     match action {
-        Action::Init => {
-            let mut init_handler = InitHandler::new(docker, sqlite);
-            init_handler.handle(current_path, shared_resources);
+        Action::Init {
+            path,
+            template,
+            security,
+            network,
+            name,
+        } => {
+            let project_path = match project_path(current_path, path.as_deref()) {
+                Ok(p) => p,
+                Err(err) => {
+                    error!("{err}");
+                    return 1;
+                }
+            };
+            let mut init_handler = InitHandler::new(runtime, sqlite);
+            init_handler.handle(
+                &project_path,
+                shared_resources,
+                template,
+                security,
+                network,
+                name,
+            );
         }
-        Action::Enter(specifier) => {
-            let mut enter_handler = EnterHandler::new(docker, sqlite);
-            enter_handler.handle(current_path, specifier);
+        Action::Enter {
+            specifier,
+            service,
+            shell,
+        } => {
+            let mut enter_handler = EnterHandler::new(runtime, sqlite);
+            enter_handler.handle(
+                current_path,
+                specifier,
+                service.as_deref(),
+                shell.as_deref(),
+                options.interactive,
+            );
         }
         Action::Kill(specifier) => {
-            let mut kill_handler = KillHandler::new(docker, sqlite);
-            kill_handler.handle(current_path, specifier);
+            let mut kill_handler = KillHandler::new(runtime, sqlite);
+            kill_handler.handle(current_path, specifier, options.interactive);
+        }
+        Action::Stop(specifier) => {
+            let mut stop_handler = StopHandler::new(runtime, sqlite);
+            stop_handler.handle(current_path, specifier, options.interactive);
+        }
+        Action::Start(specifier) => {
+            let mut start_handler = StartHandler::new(runtime, sqlite);
+            start_handler.handle(current_path, specifier, options.interactive);
+        }
+        Action::Rebuild(specifier) => {
+            let mut rebuild_handler = RebuildHandler::new(runtime, sqlite);
+            rebuild_handler.handle(
+                current_path,
+                specifier,
+                shared_resources,
+                options.interactive,
+            );
+        }
+        Action::Status { fix } => {
+            let mut status_handler = StatusHandler::new(runtime, sqlite);
+            status_handler.handle(fix, options.format);
         }
-        Action::List => {
-            let mut list_handler = ListHandler::new(docker, sqlite);
-            list_handler.handle();
+        Action::Gc { dry_run } => {
+            let mut gc_handler = GcHandler::new(runtime, sqlite);
+            gc_handler.handle(shared_resources, dry_run, options.format);
         }
+        Action::TemplateList => {
+            let mut template_handler = TemplateHandler::new(sqlite);
+            template_handler.handle_list(shared_resources, options.format);
+        }
+        Action::Rename { specifier, name } => {
+            let mut rename_handler = RenameHandler::new(runtime, sqlite);
+            rename_handler.handle(current_path, specifier, &name, options.interactive);
+        }
+        Action::Port(specifier) => {
+            let mut port_handler = PortHandler::new(runtime, sqlite);
+            port_handler.handle(current_path, specifier, options.format, options.interactive);
+        }
+        Action::List { long } => {
+            let mut list_handler = ListHandler::new(runtime, sqlite);
+            list_handler.handle(long, options.format);
+        }
+        Action::Exec {
+            specifier,
+            service,
+            argv,
+            tty,
+        } => {
+            let mut exec_handler = ExecHandler::new(runtime, sqlite);
+            return exec_handler.handle(
+                current_path,
+                specifier,
+                service.as_deref(),
+                &argv,
+                tty,
+                options.interactive,
+            );
+        }
+    }
+
+    0
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use crate::infra::sqlite::tests::{TempDb, record};
+
+    use super::*;
+
+    #[test]
+    fn find_by_ancestors_returns_nearest_environment() {
+        let db = TempDb::new();
+        let dir = env::temp_dir().join(format!("roxy-ancestors-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let outer = dir.join("outer");
+        let inner = outer.join("inner");
+        fs::create_dir_all(inner.join("src").join("deep")).unwrap();
+        fs::create_dir_all(outer.join("docs")).unwrap();
+        std::os::unix::fs::symlink(&outer, dir.join("link")).unwrap();
+
+        let mut store = SqliteForContainerStore::new(&db.0).unwrap();
+        store.insert(&record(&outer, "outer")).unwrap();
+        store.insert(&record(&inner, "inner")).unwrap();
+
+        let names = |store: &mut SqliteForContainerStore, path: &Path| {
+            find_by_ancestors(store, path)
+                .unwrap()
+                .into_iter()
+                .map(|record| record.spec.project_name)
+                .collect::<Vec<_>>()
+        };
+
+        // 紐づいたディレクトリそのもの
+        assert_eq!(names(&mut store, &outer), ["outer"]);
+        // 子孫からは最も近い祖先の環境
+        assert_eq!(
+            names(&mut store, &inner.join("src").join("deep")),
+            ["inner"]
+        );
+        assert_eq!(names(&mut store, &outer.join("docs")), ["outer"]);
+        // シンボリックリンクを経由しても同じ環境
+        assert_eq!(
+            names(&mut store, &dir.join("link").join("inner")),
+            ["inner"]
+        );
+        assert_eq!(names(&mut store, &dir.join("link").join("docs")), ["outer"]);
+        // どの環境にも紐づいていない
+        assert!(names(&mut store, &dir).is_empty());
+
+        let _ = fs::remove_dir_all(&dir);
     }
 }
@@ -0,0 +1,172 @@
+use log::error;
+use serde::Serialize;
+use serde_json::Value;
+use tabled::Table;
+
+use crate::domain::repo::Error;
+
+// 環境の情報などを出力する形式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    Table,
+    Json,
+    Yaml,
+    Tsv,
+}
+
+// 表の場合はtableを、それ以外の場合はrowsをシリアライズして出力する
+pub(super) fn print_rows<T: Serialize>(format: OutputFormat, table: Table, rows: &[T]) {
+    match format_rows(format, table, rows) {
+        Ok(output) if output.is_empty() => {}
+        Ok(output) => println!("{output}"),
+        Err(err) => error!("Failed to serialize the output: {err}"),
+    }
+}
+
+fn format_rows<T: Serialize>(
+    format: OutputFormat,
+    table: Table,
+    rows: &[T],
+) -> Result<String, Error> {
+    match format {
+        OutputFormat::Table => Ok(table.to_string()),
+        OutputFormat::Json => serde_json::to_string_pretty(rows).map_err(Error::Json),
+        OutputFormat::Yaml => serde_yaml::to_string(rows)
+            .map(|s| s.trim_end().to_string())
+            .map_err(Error::YamlSer),
+        OutputFormat::Tsv => to_tsv(rows),
+    }
+}
+
+// 1行目を見出しとするタブ区切りの文字列に変換する (行がない場合は空文字列)
+// 入れ子になった値はJSONとして1つの列に収める
+fn to_tsv<T: Serialize>(rows: &[T]) -> Result<String, Error> {
+    let rows = rows
+        .iter()
+        .map(|row| serde_json::to_value(row).map_err(Error::Json))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let Some(Value::Object(first)) = rows.first() else {
+        return Ok(String::new());
+    };
+    let headers = first.keys().cloned().collect::<Vec<_>>();
+
+    let header_line = headers
+        .iter()
+        .map(|header| escape_tsv(header))
+        .collect::<Vec<_>>();
+    let mut lines = vec![header_line.join("\t")];
+    for row in &rows {
+        let fields = headers
+            .iter()
+            .map(|header| match row.get(header) {
+                None | Some(Value::Null) => String::new(),
+                Some(Value::String(s)) => escape_tsv(s),
+                Some(value) => escape_tsv(&value.to_string()),
+            })
+            .collect::<Vec<_>>();
+        lines.push(fields.join("\t"));
+    }
+    Ok(lines.join("\n"))
+}
+
+// 列や行の区切りと紛れないようにタブと改行をエスケープする
+fn escape_tsv(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('\t', "\\t")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r")
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use tabled::Tabled;
+    use tabled::settings::Style;
+
+    use super::*;
+
+    #[derive(Serialize, Tabled)]
+    struct Row {
+        name: String,
+        port: u16,
+        #[tabled(skip)]
+        note: Option<String>,
+        #[tabled(skip)]
+        tags: Vec<String>,
+    }
+
+    fn rows() -> Vec<Row> {
+        vec![
+            Row {
+                name: "a\tb\nc\\d\re".to_string(),
+                port: 3333,
+                note: None,
+                tags: vec!["x".to_string(), "y".to_string()],
+            },
+            Row {
+                name: "plain".to_string(),
+                port: 3334,
+                note: Some("n".to_string()),
+                tags: Vec::new(),
+            },
+        ]
+    }
+
+    fn format(format: OutputFormat, rows: &[Row]) -> String {
+        let mut table = Table::new(rows);
+        table.with(Style::blank());
+        format_rows(format, table, rows).unwrap()
+    }
+
+    #[test]
+    fn table_format_renders_columns() {
+        let output = format(OutputFormat::Table, &rows()[1..]);
+        let lines = output.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].contains("name") && lines[0].contains("port"));
+        assert!(lines[1].contains("plain") && lines[1].contains("3334"));
+        // スキップした列は表示しない
+        assert!(!lines[0].contains("note"));
+    }
+
+    #[test]
+    fn json_format_keeps_values() {
+        let output = format(OutputFormat::Json, &rows());
+        let value: Value = serde_json::from_str(&output).unwrap();
+        assert_eq!(value[0]["name"], json!("a\tb\nc\\d\re"));
+        assert_eq!(value[0]["note"], Value::Null);
+        assert_eq!(value[0]["tags"], json!(["x", "y"]));
+        assert_eq!(value[1]["port"], json!(3334));
+    }
+
+    #[test]
+    fn yaml_format_keeps_values() {
+        let output = format(OutputFormat::Yaml, &rows());
+        assert!(!output.ends_with('\n'));
+        let value: serde_yaml::Value = serde_yaml::from_str(&output).unwrap();
+        assert_eq!(value[0]["name"].as_str(), Some("a\tb\nc\\d\re"));
+        assert_eq!(value[1]["note"].as_str(), Some("n"));
+    }
+
+    #[test]
+    fn tsv_format_escapes_separators() {
+        let output = format(OutputFormat::Tsv, &rows());
+        let lines = output.split('\n').collect::<Vec<_>>();
+        assert_eq!(
+            lines,
+            [
+                "name\tport\tnote\ttags",
+                "a\\tb\\nc\\\\d\\re\t3333\t\t[\"x\",\"y\"]",
+                "plain\t3334\tn\t[]",
+            ]
+        );
+    }
+
+    #[test]
+    fn tsv_format_of_no_rows_is_empty() {
+        assert_eq!(format(OutputFormat::Tsv, &[]), "");
+        assert_eq!(format(OutputFormat::Json, &[]), "[]");
+    }
+}
@@ -0,0 +1,86 @@
+use std::path::Path;
+
+use log::{info, warn};
+use tabled::Table;
+use tabled::settings::Style;
+
+use crate::domain::repo::{ContainerInfo, EnvRecord, EnvSpecifier, EnvStore, PortMapping, Runtime};
+
+use super::output::{OutputFormat, print_rows};
+use super::specify_env_to_operate;
+
+pub(crate) struct PortHandler<R: Runtime, S: EnvStore> {
+    runtime: R,
+    env_store: S,
+}
+
+impl<R: Runtime, S: EnvStore> PortHandler<R, S> {
+    pub fn new(runtime: R, env_store: S) -> Self {
+        Self { runtime, env_store }
+    }
+
+    // 環境が公開しているポートと、割り当てたホストのポートを出力する
+    pub fn handle(
+        &mut self,
+        current_path: &Path,
+        env_specifier: Option<EnvSpecifier>,
+        format: OutputFormat,
+        interactive: bool,
+    ) {
+        if let Some(env_record) = specify_env_to_operate(
+            &mut self.runtime,
+            &mut self.env_store,
+            current_path,
+            env_specifier,
+            interactive,
+        ) {
+            let ports = &self.ports(&env_record);
+
+            // 表以外の形式では公開しているポートがなくても空の一覧を出力する
+            if ports.is_empty() {
+                info!("{} publishes no ports.", env_record.spec.project_name);
+                if format != OutputFormat::Table {
+                    print_rows(format, Table::new(ports), ports);
+                }
+                return;
+            }
+
+            let mut table = Table::new(ports);
+            table.with(Style::blank());
+
+            print_rows(format, table, ports);
+        }
+    }
+
+    // 記録されたポートを返す
+    // ports列を追加する前に作られた環境は記録が空なので、設定ディレクトリから読み出して記録しておく
+    fn ports(&mut self, env_record: &EnvRecord) -> Vec<PortMapping> {
+        if !env_record.container_info.ports.is_empty() {
+            return env_record.container_info.ports.clone();
+        }
+
+        let ports = match self.runtime.published_ports(env_record) {
+            Ok(ports) => ports,
+            Err(err) => {
+                warn!(
+                    "Failed to read the ports of {}: {err}",
+                    env_record.spec.project_name
+                );
+                return Vec::new();
+            }
+        };
+        if !ports.is_empty() {
+            let container_info = ContainerInfo {
+                ports: ports.clone(),
+                ..env_record.container_info.clone()
+            };
+            if let Err(err) = self
+                .env_store
+                .update_container(env_record.spec.uuid, &container_info)
+            {
+                warn!("Failed to update the environment record: {err}");
+            }
+        }
+        ports
+    }
+}
@@ -0,0 +1,94 @@
+use std::path::Path;
+
+use log::{error, info};
+
+use crate::domain::config::ProjectConfig;
+use crate::domain::repo::{EnvSpecifier, EnvStore, Runtime, SharedResources};
+
+use super::{lock_envs, specify_env_to_operate};
+
+pub(crate) struct RebuildHandler<R: Runtime, S: EnvStore> {
+    runtime: R,
+    env_store: S,
+}
+
+impl<R: Runtime, S: EnvStore> RebuildHandler<R, S> {
+    pub fn new(runtime: R, env_store: S) -> Self {
+        Self { runtime, env_store }
+    }
+
+    pub fn handle(
+        &mut self,
+        current_path: &Path,
+        env_specifier: Option<EnvSpecifier>,
+        shared_resources: &SharedResources,
+        interactive: bool,
+    ) {
+        if let Some(env_record) = specify_env_to_operate(
+            &mut self.runtime,
+            &mut self.env_store,
+            current_path,
+            env_specifier,
+            interactive,
+        ) {
+            info!("Rebuilding {}", env_record.spec.project_name);
+
+            // プロジェクトの設定を読み込み直す
+            let mut env_record = env_record;
+            let mut config = match ProjectConfig::load(&env_record.spec.project_path) {
+                Ok(c) => c,
+                Err(err) => {
+                    error!("Failed to load the project configuration: {err}");
+                    return;
+                }
+            };
+            // 設定ファイルでテンプレートが指定されていない場合は作成時のテンプレートを使い続ける
+            if config.template.is_none() {
+                config.template = env_record.spec.config.template.take();
+            }
+            // セキュリティプロファイルとネットワークも同様に作成時のものを使い続ける
+            if config.security.is_none() {
+                config.security = env_record.spec.config.security;
+            }
+            if config.network.is_none() {
+                config.network = env_record.spec.config.network;
+            }
+            env_record.spec.template_hash =
+                match shared_resources.template_hash(config.template.as_deref()) {
+                    Ok(h) => Some(h),
+                    Err(err) => {
+                        error!("Failed to read the template: {err}");
+                        return;
+                    }
+                };
+            env_record.spec.config = config;
+
+            // 現在のテンプレートからコンテナを作り直す (記録を更新するまでgcやinitと排他する)
+            let Some(_lock) = lock_envs(shared_resources) else {
+                return;
+            };
+            let container_info = match self.runtime.rebuild(shared_resources, &env_record) {
+                Ok(i) => i,
+                Err(err) => {
+                    error!("Failed to rebuild the environment: {err}");
+                    return;
+                }
+            };
+
+            // 新しい設定とコンテナの情報を保存する
+            if let Err(err) = self
+                .env_store
+                .update_spec(env_record.spec.uuid, &env_record.spec)
+            {
+                error!("Failed to update the environment record: {err}");
+            }
+
+            if let Err(err) = self
+                .env_store
+                .update_container(env_record.spec.uuid, &container_info)
+            {
+                error!("Failed to update the environment record: {err}");
+            }
+        }
+    }
+}
@@ -0,0 +1,51 @@
+use std::path::Path;
+
+use log::{error, info};
+
+use crate::domain::repo::{EnvSpecifier, EnvStore, Runtime};
+
+use super::{is_valid_name, specify_env_to_operate};
+
+pub(crate) struct RenameHandler<R: Runtime, S: EnvStore> {
+    runtime: R,
+    env_store: S,
+}
+
+impl<R: Runtime, S: EnvStore> RenameHandler<R, S> {
+    pub fn new(runtime: R, env_store: S) -> Self {
+        Self { runtime, env_store }
+    }
+
+    pub fn handle(
+        &mut self,
+        current_path: &Path,
+        env_specifier: Option<EnvSpecifier>,
+        name: &str,
+        interactive: bool,
+    ) {
+        if !is_valid_name(name) {
+            error!("Invalid environment name: {name:?}");
+            return;
+        }
+
+        if let Some(env_record) = specify_env_to_operate(
+            &mut self.runtime,
+            &mut self.env_store,
+            current_path,
+            env_specifier,
+            interactive,
+        ) {
+            if env_record.spec.project_name == name {
+                info!("{name} already has that name.");
+                return;
+            }
+
+            info!("Renaming {} to {name}", env_record.spec.project_name);
+
+            // 名前は他の環境と重複してはならない (重複する場合はストアがEnvConflictを返す)
+            if let Err(err) = self.env_store.rename(env_record.spec.uuid, name) {
+                error!("Failed to rename the environment: {err}");
+            }
+        }
+    }
+}
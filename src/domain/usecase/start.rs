@@ -0,0 +1,40 @@
+use std::path::Path;
+
+use log::info;
+
+use crate::domain::repo::{EnvSpecifier, EnvState, EnvStore, Runtime};
+
+use super::{ensure_running, specify_env_to_operate};
+
+pub(crate) struct StartHandler<R: Runtime, S: EnvStore> {
+    runtime: R,
+    env_store: S,
+}
+
+impl<R: Runtime, S: EnvStore> StartHandler<R, S> {
+    pub fn new(runtime: R, env_store: S) -> Self {
+        Self { runtime, env_store }
+    }
+
+    pub fn handle(
+        &mut self,
+        current_path: &Path,
+        env_specifier: Option<EnvSpecifier>,
+        interactive: bool,
+    ) {
+        if let Some(env_record) = specify_env_to_operate(
+            &mut self.runtime,
+            &mut self.env_store,
+            current_path,
+            env_specifier,
+            interactive,
+        ) {
+            if env_record.container_info.state == EnvState::Running {
+                info!("{} is already running.", env_record.spec.project_name);
+                return;
+            }
+
+            ensure_running(&mut self.runtime, &mut self.env_store, &env_record);
+        }
+    }
+}
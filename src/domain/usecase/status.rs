@@ -0,0 +1,143 @@
+use log::{error, info, warn};
+use tabled::Table;
+use tabled::settings::Style;
+
+use crate::domain::repo::{
+    ContainerForStatus, ContainerStatus, EnvRecord, EnvState, EnvStore, Runtime,
+};
+
+use super::env_status;
+use super::output::{OutputFormat, print_rows};
+
+pub(crate) struct StatusHandler<R: Runtime, S: EnvStore> {
+    runtime: R,
+    env_store: S,
+}
+
+impl<R: Runtime, S: EnvStore> StatusHandler<R, S> {
+    pub fn new(runtime: R, env_store: S) -> Self {
+        Self { runtime, env_store }
+    }
+
+    pub fn handle(&mut self, fix: bool, format: OutputFormat) {
+        let mut env_records = match self.env_store.list() {
+            Ok(v) => v,
+            Err(err) => {
+                error!("Failed to get list of environments: {err:?}");
+                return;
+            }
+        };
+
+        // 記録と実際のコンテナの状態を一致させてから表示する
+        if fix {
+            for env_record in &env_records {
+                self.reconcile(env_record);
+            }
+            env_records = match self.env_store.list() {
+                Ok(v) => v,
+                Err(err) => {
+                    error!("Failed to get list of environments: {err:?}");
+                    return;
+                }
+            };
+        }
+
+        // コンテナごとの状態を出力する
+        let mut containers = Vec::new();
+        for env_record in &env_records {
+            for (service, container_id) in env_record.container_info.service_containers() {
+                let status = match self.runtime.inspect(env_record, container_id) {
+                    Ok(status) => status.to_string(),
+                    Err(err) => {
+                        warn!("Failed to inspect {container_id}: {err}");
+                        "unknown".into()
+                    }
+                };
+                containers.push(ContainerForStatus {
+                    name: env_record.spec.project_name.clone(),
+                    service: service.map(String::from),
+                    container_id: container_id.clone(),
+                    state: env_record.container_info.state,
+                    status,
+                });
+            }
+        }
+
+        let mut table = Table::new(&containers);
+        table.with(Style::blank());
+
+        print_rows(format, table, &containers);
+    }
+
+    // コンテナが見つからない環境はコンテナを探し直し、見つからなければ記録を削除する
+    // コンテナが揃っている環境は記録された状態を実際の状態に合わせる
+    fn reconcile(&mut self, env_record: &EnvRecord) {
+        let name = &env_record.spec.project_name;
+
+        let status = match env_status(&mut self.runtime, env_record) {
+            Ok(status) => status,
+            Err(err) => {
+                error!("Failed to inspect {name}: {err}");
+                return;
+            }
+        };
+
+        let env_record = if status == ContainerStatus::Missing {
+            let container_info = match self.runtime.refresh(env_record) {
+                Ok(Some(container_info)) => container_info,
+                Ok(None) => {
+                    info!("Removing {name} because its containers no longer exist.");
+                    if let Err(err) = self.env_store.remove_by_uuid(env_record.spec.uuid) {
+                        error!("Failed to remove the environment record: {err}");
+                    }
+                    return;
+                }
+                Err(err) => {
+                    error!("Failed to look up the containers of {name}: {err}");
+                    return;
+                }
+            };
+
+            info!("Refreshing the container ids of {name}");
+            if let Err(err) = self
+                .env_store
+                .update_container(env_record.spec.uuid, &container_info)
+            {
+                error!("Failed to update the environment record: {err}");
+                return;
+            }
+            EnvRecord {
+                spec: env_record.spec.clone(),
+                container_info,
+                timestamps: env_record.timestamps.clone(),
+            }
+        } else {
+            env_record.clone()
+        };
+
+        let state = match env_status(&mut self.runtime, &env_record) {
+            Ok(ContainerStatus::Running) => EnvState::Running,
+            Ok(ContainerStatus::Exited) => EnvState::Stopped,
+            // 一時停止はroxyの外で行われたものなので、記録された状態は変えない
+            Ok(ContainerStatus::Paused) => {
+                warn!("Some containers of {name} are paused.");
+                return;
+            }
+            Ok(ContainerStatus::Missing) => {
+                warn!("Some containers of {name} are still missing.");
+                return;
+            }
+            Err(err) => {
+                error!("Failed to inspect {name}: {err}");
+                return;
+            }
+        };
+
+        if state != env_record.container_info.state {
+            info!("Marking {name} as {state}");
+            if let Err(err) = self.env_store.update_state(env_record.spec.uuid, state) {
+                error!("Failed to update the environment record: {err}");
+            }
+        }
+    }
+}
@@ -0,0 +1,54 @@
+use std::path::Path;
+
+use log::{error, info};
+
+use crate::domain::repo::{EnvSpecifier, EnvState, EnvStore, Runtime};
+
+use super::specify_env_to_operate;
+
+pub(crate) struct StopHandler<R: Runtime, S: EnvStore> {
+    runtime: R,
+    env_store: S,
+}
+
+impl<R: Runtime, S: EnvStore> StopHandler<R, S> {
+    pub fn new(runtime: R, env_store: S) -> Self {
+        Self { runtime, env_store }
+    }
+
+    pub fn handle(
+        &mut self,
+        current_path: &Path,
+        env_specifier: Option<EnvSpecifier>,
+        interactive: bool,
+    ) {
+        if let Some(env_record) = specify_env_to_operate(
+            &mut self.runtime,
+            &mut self.env_store,
+            current_path,
+            env_specifier,
+            interactive,
+        ) {
+            if env_record.container_info.state == EnvState::Stopped {
+                info!("{} is already stopped.", env_record.spec.project_name);
+                return;
+            }
+
+            info!("Stopping {}", env_record.spec.project_name);
+
+            // コンテナを停止する
+            if let Err(err) = self.runtime.stop(&env_record) {
+                error!("Failed to stop the environment: {err}");
+                return;
+            }
+
+            // 停止したことを記録する
+            if let Err(err) = self
+                .env_store
+                .update_state(env_record.spec.uuid, EnvState::Stopped)
+            {
+                error!("Failed to update the environment record: {err}");
+            }
+        }
+    }
+}
@@ -0,0 +1,75 @@
+use log::error;
+use serde::Serialize;
+use tabled::settings::Style;
+use tabled::{Table, Tabled};
+
+use crate::domain::repo::{DEFAULT_TEMPLATE, EnvStore, SharedResources};
+
+use super::output::{OutputFormat, print_rows};
+
+#[derive(Tabled, Serialize)]
+struct TemplateForList {
+    name: String,
+    path: String,
+    environments: usize,
+}
+
+pub(crate) struct TemplateHandler<S: EnvStore> {
+    env_store: S,
+}
+
+impl<S: EnvStore> TemplateHandler<S> {
+    pub fn new(env_store: S) -> Self {
+        Self { env_store }
+    }
+
+    pub fn handle_list(&mut self, shared_resources: &SharedResources, format: OutputFormat) {
+        // 利用できるプロファイルの一覧を取得する
+        let names = match shared_resources.template_names() {
+            Ok(v) => v,
+            Err(err) => {
+                error!("Failed to get list of templates: {err}");
+                return;
+            }
+        };
+
+        // 各プロファイルを使っている環境の数を数えるために環境の一覧を取得する
+        let env_records = match self.env_store.list() {
+            Ok(v) => v,
+            Err(err) => {
+                error!("Failed to get list of environments: {err:?}");
+                return;
+            }
+        };
+
+        let templates = names
+            .into_iter()
+            .map(|name| {
+                let environments = env_records
+                    .iter()
+                    .filter(|r| {
+                        r.spec
+                            .config
+                            .template
+                            .as_deref()
+                            .unwrap_or(DEFAULT_TEMPLATE)
+                            == name
+                    })
+                    .count();
+                TemplateForList {
+                    path: shared_resources
+                        .template_dir(Some(&name))
+                        .display()
+                        .to_string(),
+                    name,
+                    environments,
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let mut table = Table::new(&templates);
+        table.with(Style::blank());
+
+        print_rows(format, table, &templates);
+    }
+}
@@ -0,0 +1,1551 @@
+use indexmap::IndexMap;
+use serde::{Deserialize, Serialize};
+use serde_yaml::{Mapping, Value};
+use std::collections::{HashMap, HashSet};
+use std::io::{Read, Write};
+use std::net::{IpAddr, Ipv4Addr, TcpListener, UdpSocket};
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::process::{CommandExt, ExitStatusExt};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::{env, fs, io};
+
+use log::{debug, info};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use crate::domain::config::{NetworkMode, ProjectConfig, SecurityProfile};
+use crate::domain::repo::{
+    ContainerId, ContainerStatus, EnvSpec, Error, PortMapping, RuntimeKind, SharedResources,
+};
+use crate::util;
+
+use super::render;
+
+pub const DOCKERFILE_NAME: &str = "dockerfile";
+pub const COMPOSE_NAME: &str = "compose.yml";
+pub const DOCKERIGNORE_NAME: &str = ".dockerignore";
+// 設定ディレクトリの名前はroxy-<uuid> (composeのproject名になる)
+pub const CONFIG_DIR_PREFIX: &str = "roxy-";
+// 以前のバージョンが設定ディレクトリを作成していた場所
+pub const LEGACY_CONFIG_ROOT: &str = "/tmp";
+// /tmpからの移行を終えたことを示すファイルの名前 (設定ディレクトリの親に置く)
+const LEGACY_MIGRATED_MARKER: &str = ".legacy-migrated";
+
+// compose.ymlのうちroxyが解釈する拡張フィールドのキー
+pub const EXTENSION_KEY: &str = "x-roxy";
+
+// 主サービスをホストのユーザーと同じUID/GIDで実行することを表すuserの値
+pub const HOST_USER: &str = "host";
+
+// roxyがビルドして環境のあいだで共有するイメージの名前の接頭辞 (roxy-<service>:<hash>)
+pub const IMAGE_PREFIX: &str = "roxy-";
+
+// x-roxyのworkspaceが指定されていない場合に、プロジェクトのディレクトリをマウントするパス
+pub const DEFAULT_WORKSPACE: &str = "/root/workspace";
+
+// x-roxyのport_rangeが指定されていない場合に、ホストのポートを割り当てる範囲
+pub const DEFAULT_PORT_RANGE: (u16, u16) = (20000, 20999);
+
+// share_imagesで共有するイメージに置き換えられるサービスのbuildのキー
+pub const SHARED_BUILD_KEYS: &[&str] = &["context", "dockerfile", "args"];
+
+// シェルが設定されていない場合に、この順でコンテナ内を探す
+pub const SHELL_CANDIDATES: &[&str] = &["fish", "zsh", "bash", "sh"];
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Compose {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+
+    #[serde(default)]
+    pub services: IndexMap<String, Service>,
+
+    #[serde(flatten)]
+    pub other: IndexMap<String, Value>,
+}
+
+// compose.ymlのx-roxyに書かれたテンプレートの設定
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Extension {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub shell: Option<String>,
+
+    // 環境に入るときやコマンドを実行するときに使うサービスの名前
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub primary: Option<String>,
+
+    // 主サービスを実行するユーザー ("host"ならホストのユーザーと同じUID/GID)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+
+    // テンプレートのポートに割り当てるホストのポートの範囲 ("20000-20999")
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub port_range: Option<String>,
+
+    // プロジェクトのディレクトリをマウントする主サービス内のパス
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub workspace: Option<String>,
+}
+
+impl Extension {
+    // port_rangeを(最初のポート, 最後のポート)に変換する
+    pub fn port_range(&self) -> Result<(u16, u16), Error> {
+        let Some(range) = &self.port_range else {
+            return Ok(DEFAULT_PORT_RANGE);
+        };
+        let invalid = || Error::InvalidComposeConfig {
+            reason: format!("invalid port_range: {range:?} (expected \"start-end\")"),
+        };
+        let (start, end) = range.split_once('-').ok_or_else(invalid)?;
+        let start = start.trim().parse::<u16>().map_err(|_| invalid())?;
+        let end = end.trim().parse::<u16>().map_err(|_| invalid())?;
+        if start == 0 || start > end {
+            return Err(invalid());
+        }
+        Ok((start, end))
+    }
+}
+
+impl Compose {
+    pub fn extension(&self) -> Result<Extension, Error> {
+        match self.other.get(EXTENSION_KEY) {
+            Some(v) => serde_yaml::from_value(v.clone()).map_err(Error::YamlSer),
+            None => Ok(Extension::default()),
+        }
+    }
+
+    // buildが残っている (share_imagesで共有するイメージに置き換えられなかった) サービスがあるかどうか
+    pub fn has_builds(&self) -> bool {
+        self.services
+            .values()
+            .any(|service| service.other.contains_key("build"))
+    }
+
+    // 主サービスの名前を返す
+    // x-roxyのprimaryで指定されていない場合は先頭のサービスを主サービスとする
+    pub fn primary_service(&self) -> Result<String, Error> {
+        match self.extension()?.primary {
+            Some(primary) if self.services.contains_key(&primary) => Ok(primary),
+            Some(primary) => Err(Error::InvalidComposeConfig {
+                reason: format!("primary service {primary} is not defined in services"),
+            }),
+            None => {
+                self.services
+                    .keys()
+                    .next()
+                    .cloned()
+                    .ok_or_else(|| Error::InvalidComposeConfig {
+                        reason: "services must not be empty".into(),
+                    })
+            }
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Service {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub volumes: Option<Vec<Value>>,
+
+    #[serde(flatten)]
+    pub other: IndexMap<String, Value>,
+}
+
+impl Service {
+    // network_modeが指定されたサービスはポートを公開できない
+    fn publishes_ports(&self) -> bool {
+        !self.other.contains_key("network_mode")
+    }
+}
+
+// 設定ディレクトリをビルドコンテキストとするサービスのイメージ
+// タグはdockerfileとビルド引数から決まるので、同じテンプレートから作る環境は同じイメージを使う
+#[derive(Debug)]
+pub struct ImageBuild {
+    pub tag: String,
+    pub context: PathBuf,
+    pub dockerfile: String,
+    pub args: IndexMap<String, String>,
+}
+
+// portsの要素 ("[ip:][host:]container[/proto]") を分解したもの
+struct PortSpec {
+    host_ip: Option<String>,
+    host_port: Option<u16>,
+    container_port: u16,
+    protocol: String,
+}
+
+impl PortSpec {
+    fn parse(value: &Value) -> Result<Self, Error> {
+        let invalid = || Error::InvalidComposeConfig {
+            reason: format!(
+                "unsupported port: {value:?} (expected \"[ip:][host:]container[/proto]\")"
+            ),
+        };
+        let port = match value {
+            Value::String(s) => s.clone(),
+            Value::Number(n) => n.to_string(),
+            _ => return Err(invalid()),
+        };
+        let (port, protocol) = port.split_once('/').unwrap_or((&port, "tcp"));
+
+        // IPv6のアドレスにも:が含まれるので右から分ける
+        let mut parts = port.rsplitn(3, ':');
+        let container_port = parts.next().ok_or_else(invalid)?;
+        let host_port = parts.next().filter(|p| !p.is_empty());
+        let host_ip = parts.next().filter(|ip| !ip.is_empty());
+
+        Ok(Self {
+            host_ip: host_ip.map(String::from),
+            host_port: host_port
+                .map(|p| p.parse::<u16>().map_err(|_| invalid()))
+                .transpose()?,
+            container_port: container_port.parse::<u16>().map_err(|_| invalid())?,
+            protocol: protocol.to_string(),
+        })
+    }
+
+    fn to_value(&self) -> Value {
+        let mut port = String::new();
+        if let Some(host_ip) = &self.host_ip {
+            port.push_str(&format!("{host_ip}:"));
+        }
+        if let Some(host_port) = self.host_port {
+            port.push_str(&format!("{host_port}:"));
+        }
+        port.push_str(&format!("{}/{}", self.container_port, self.protocol));
+        Value::String(port)
+    }
+
+    // ホストのポートが決まっている場合はPortMappingに変換する
+    fn to_mapping(&self, service: &str) -> Option<PortMapping> {
+        Some(PortMapping {
+            service: service.to_string(),
+            container_port: self.container_port,
+            protocol: self.protocol.clone(),
+            host_ip: self.host_ip.clone(),
+            host_port: self.host_port?,
+        })
+    }
+}
+
+// compose.ymlで公開されているポートをすべて返す (ホストのポートが決まっていないものは除く)
+pub fn published_ports(compose: &Compose) -> Result<Vec<PortMapping>, Error> {
+    let mut mappings = Vec::new();
+    for (name, service) in &compose.services {
+        if !service.publishes_ports() {
+            continue;
+        }
+        let Some(Value::Sequence(ports)) = service.other.get("ports") else {
+            continue;
+        };
+        for port in ports {
+            mappings.extend(PortSpec::parse(port)?.to_mapping(name));
+        }
+    }
+    Ok(mappings)
+}
+
+// 設定ディレクトリを置くディレクトリ ($XDG_STATE_HOME/roxy/envs) を返す
+pub fn config_root_path() -> PathBuf {
+    let state_home = env::var_os("XDG_STATE_HOME")
+        .map(PathBuf::from)
+        .filter(|path| path.is_absolute())
+        .or_else(|| env::home_dir().map(|home| home.join(".local").join("state")))
+        .unwrap_or_else(env::temp_dir);
+    state_home.join("roxy").join("envs")
+}
+
+// 環境ごとの設定ディレクトリのパスを返す
+pub fn config_dir_path(uuid: Uuid) -> PathBuf {
+    config_root_path().join(format!("{CONFIG_DIR_PREFIX}{uuid}"))
+}
+
+// 設定ディレクトリを置き換えるときに使う作業用のディレクトリのパスを返す
+// 先頭に.を付けるので、設定ディレクトリとしては列挙されない
+fn staging_dir_path(uuid: Uuid, suffix: &str) -> PathBuf {
+    config_root_path().join(format!(".{CONFIG_DIR_PREFIX}{uuid}.{suffix}"))
+}
+
+// /tmpに残っている設定ディレクトリを新しい場所に移す
+// 移動先に既に存在する場合は新しい場所のものを優先して、古いものは残しておく
+// 一度すべて移し終えたら印のファイルを置き、以降は/tmpを調べない
+pub fn migrate_legacy_config_dirs() -> Result<(), Error> {
+    let legacy_root = Path::new(LEGACY_CONFIG_ROOT);
+    let config_root = config_root_path();
+    let marker = config_root.join(LEGACY_MIGRATED_MARKER);
+    if marker.exists() {
+        return Ok(());
+    }
+
+    for (uuid, legacy_path) in list_config_dirs(legacy_root)? {
+        // /tmpは共有されているので、他のユーザーのものは移さない
+        let owned = fs::metadata(&legacy_path).is_ok_and(|m| m.uid() == util::host_uid());
+        let config_path = config_dir_path(uuid);
+        if !owned || config_path.exists() {
+            continue;
+        }
+
+        fs::create_dir_all(&config_root).map_err(|err| Error::Io {
+            path: Some(config_root.clone()),
+            source: err,
+        })?;
+
+        // /tmpが別のファイルシステムの場合はrenameできないのでコピーしてから削除する
+        if fs::rename(&legacy_path, &config_path).is_err() {
+            copy_dir_all(&legacy_path, &config_path)?;
+            fs::remove_dir_all(&legacy_path).map_err(|err| Error::Io {
+                path: Some(legacy_path.clone()),
+                source: err,
+            })?;
+        }
+        debug!(
+            "Moved {} to {}",
+            legacy_path.display(),
+            config_path.display()
+        );
+    }
+
+    fs::create_dir_all(&config_root)
+        .and_then(|_| fs::write(&marker, ""))
+        .map_err(|err| Error::Io {
+            path: Some(marker),
+            source: err,
+        })
+}
+
+// ディレクトリを中身ごとコピーする
+fn copy_dir_all(from: &Path, to: &Path) -> Result<(), Error> {
+    let io_error = |path: &Path| {
+        let path = path.to_path_buf();
+        move |err| Error::Io {
+            path: Some(path),
+            source: err,
+        }
+    };
+
+    fs::create_dir_all(to).map_err(io_error(to))?;
+    for entry in fs::read_dir(from).map_err(io_error(from))? {
+        let entry = entry.map_err(io_error(from))?;
+        let target = to.join(entry.file_name());
+        if entry.path().is_dir() {
+            copy_dir_all(&entry.path(), &target)?;
+        } else {
+            fs::copy(entry.path(), &target).map_err(io_error(&target))?;
+        }
+    }
+    Ok(())
+}
+
+// テンプレートから作業用の設定ディレクトリを作成し、そのパスとビルドするイメージを返す
+// 使い終わったらcommit_config_dirかdiscard_config_dirを呼ぶ
+pub fn prepare_config_dir(
+    shared_resources: &SharedResources,
+    env_spec: &EnvSpec,
+    runtime: RuntimeKind,
+) -> Result<(PathBuf, Vec<ImageBuild>), Error> {
+    // 使用するテンプレートを決める (プロジェクトの設定で指定されていなければ共有ディレクトリのもの)
+    let template = env_spec.config.template.as_deref();
+    let dockerfile_template = shared_resources.dockerfile_template_absolute_path(template);
+    let compose_template = shared_resources.compose_template_absolute_path(template);
+    for template in [&dockerfile_template, &compose_template] {
+        if !template.is_file() {
+            return Err(Error::TemplateNotFound {
+                path: template.clone(),
+            });
+        }
+    }
+
+    // 作り直す場合は、以前割り当てたホストのポートをできるだけ使い続けるために読み込んでおく
+    let previous_ports = load_compose(&config_dir_path(env_spec.uuid))
+        .and_then(|compose| published_ports(&compose))
+        .unwrap_or_default();
+
+    // 作り直す場合に展開やビルドに失敗しても動いている環境を壊さないように、作業用のディレクトリに展開する
+    // commit_config_dirを呼ぶまで設定ディレクトリは置き換えない
+    let config_path = staging_dir_path(env_spec.uuid, "new");
+    remove_dir_if_exists(&config_path)?;
+
+    fs::create_dir_all(&config_path).map_err(|err| Error::Io {
+        path: Some(config_path.clone()),
+        source: err,
+    })?;
+
+    // テンプレートの変数に代入する値を用意する
+    let variables = render::variables(env_spec);
+
+    // テンプレートのdockerfileを展開して設定ディレクトリに書き出す
+    let dockerfile = read_template(&dockerfile_template)?;
+    let dockerfile = render::render(&dockerfile, &variables, &dockerfile_template)?;
+    fs::write(config_path.join(DOCKERFILE_NAME), dockerfile).map_err(|err| Error::Io {
+        path: Some(config_path.join(DOCKERFILE_NAME)),
+        source: err,
+    })?;
+
+    // イメージは環境のあいだで共有されるので、環境ごとのcompose.ymlはビルドコンテキストに含めない
+    fs::write(
+        config_path.join(DOCKERIGNORE_NAME),
+        format!("{COMPOSE_NAME}\n"),
+    )
+    .map_err(|err| Error::Io {
+        path: Some(config_path.join(DOCKERIGNORE_NAME)),
+        source: err,
+    })?;
+
+    // テンプレートのcompose.ymlを展開してシリアライズする
+    let compose_contents = read_template(&compose_template)?;
+    let compose_value = render::render_yaml(&compose_contents, &variables, &compose_template)?;
+    let mut compose: Compose = serde_yaml::from_value(compose_value).map_err(Error::YamlSer)?;
+
+    // 主サービスを実行するユーザーを決める (プロジェクトの設定はテンプレートより優先する)
+    let extension = compose.extension()?;
+    let user = env_spec.config.user.clone().or(extension.user);
+    let workspace = extension
+        .workspace
+        .unwrap_or_else(|| DEFAULT_WORKSPACE.to_string());
+
+    // 主サービスのvolumesを編集する (他のサービスはテンプレートのまま)
+    let primary = compose.primary_service()?;
+    let service = &mut compose.services[&primary];
+
+    let volume = format!("{}:{workspace}:rw", env_spec.project_path.display());
+    let volumes = vec![Value::String(volume)];
+    service.volumes.replace(volumes);
+    // 環境に入ったときにプロジェクトのディレクトリから始まるようにする
+    service
+        .other
+        .entry("working_dir".into())
+        .or_insert(Value::String(workspace));
+
+    // プロジェクトの設定を主サービスに重ねる
+    apply_project_config(service, &env_spec.config);
+    apply_security(service, env_spec.config.security.unwrap_or_default());
+    if let Some(mode) = env_spec.config.network {
+        apply_network(service, mode);
+    }
+    if let Some(user) = user {
+        apply_user(service, &user, runtime);
+    }
+
+    // 公開するポートにホストのポートを割り当てる (コンテナを起動する前に衝突を検出する)
+    let in_use = ports_in_use(env_spec.uuid);
+    assign_host_ports(&mut compose, env_spec, &previous_ports, &in_use)?;
+
+    // buildの代わりにdockerfileのハッシュをタグとするイメージを参照させる
+    let images = share_images(&mut compose, &config_path)?;
+
+    // yamlにデシリアライズする
+    let yaml = serde_yaml::to_string(&compose).map_err(Error::YamlDe)?;
+
+    // 変更をcompose.ymlに保存する
+    let file = fs::File::create(config_path.join(COMPOSE_NAME)).map_err(|err| Error::Io {
+        path: Some(config_path.join(COMPOSE_NAME)),
+        source: err,
+    })?;
+    let mut writer = io::BufWriter::new(file);
+    writer.write_all(yaml.as_bytes()).map_err(|err| Error::Io {
+        path: Some(config_path.join(COMPOSE_NAME)),
+        source: err,
+    })?;
+
+    // 保存したあとにflushしないとcompose.ymlがからのままdocker compose upが実行されてしまうのでflushする
+    writer.flush().map_err(|err| Error::Io {
+        path: Some(config_path.join(COMPOSE_NAME)),
+        source: err,
+    })?;
+
+    Ok((config_path, images))
+}
+
+// 設定ディレクトリをビルドコンテキストとするサービスのbuildを、展開したdockerfileとビルド引数の
+// ハッシュをタグとするimageに置き換え、ビルドするイメージとして返す
+// テンプレートが変わらなければタグも変わらないので、既にあるイメージをビルドし直さずに使える
+// context、dockerfile、args以外 (targetなど) が指定されたサービスは、その内容をタグに反映できないので共有しない
+fn share_images(compose: &mut Compose, config_path: &Path) -> Result<Vec<ImageBuild>, Error> {
+    let mut images = Vec::new();
+    for (name, service) in compose.services.iter_mut() {
+        let (context, dockerfile, args) = match service.other.get("build") {
+            Some(Value::String(context)) => (context.clone(), "Dockerfile".to_string(), None),
+            Some(Value::Mapping(m))
+                if m.keys().all(|key| {
+                    key.as_str()
+                        .is_some_and(|key| SHARED_BUILD_KEYS.contains(&key))
+                }) =>
+            {
+                (
+                    m.get("context")
+                        .and_then(Value::as_str)
+                        .unwrap_or(".")
+                        .to_string(),
+                    m.get("dockerfile")
+                        .and_then(Value::as_str)
+                        .unwrap_or("Dockerfile")
+                        .to_string(),
+                    m.get("args"),
+                )
+            }
+            _ => continue,
+        };
+        // 設定ディレクトリの外にあるコンテキストの内容はハッシュに含められないので、環境ごとにビルドする
+        if !matches!(context.as_str(), "." | "./") {
+            continue;
+        }
+        let args = build_args(name, args)?;
+
+        let dockerfile_path = config_path.join(&dockerfile);
+        let contents = fs::read(&dockerfile_path).map_err(|err| Error::Io {
+            path: Some(dockerfile_path),
+            source: err,
+        })?;
+
+        // 値の境界が変わっても同じハッシュにならないように長さも含める
+        let mut hasher = Sha256::new();
+        let args_s = args.iter().map(|(k, v)| format!("{k}={v}"));
+        for part in [
+            dockerfile.clone(),
+            String::from_utf8_lossy(&contents).into(),
+        ]
+        .into_iter()
+        .chain(args_s)
+        {
+            hasher.update((part.len() as u64).to_le_bytes());
+            hasher.update(part.as_bytes());
+        }
+        let hash = hasher
+            .finalize()
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect::<String>();
+        let tag = format!("{IMAGE_PREFIX}{}:{}", name.to_lowercase(), &hash[..16]);
+
+        service.other.shift_remove("build");
+        service
+            .other
+            .insert("image".into(), Value::String(tag.clone()));
+        images.push(ImageBuild {
+            tag,
+            context: config_path.to_path_buf(),
+            dockerfile,
+            args,
+        });
+    }
+    Ok(images)
+}
+
+// build.argsをビルド引数の名前と値の組に変換する (マップと"KEY=VALUE"のリストのどちらも受け付ける)
+pub fn build_args(
+    service_name: &str,
+    args: Option<&Value>,
+) -> Result<IndexMap<String, String>, Error> {
+    let invalid = || Error::InvalidComposeConfig {
+        reason: format!("unsupported build args in service {service_name}"),
+    };
+    let scalar = |v: &Value| match v {
+        Value::String(s) => Ok(s.clone()),
+        Value::Number(n) => Ok(n.to_string()),
+        Value::Bool(b) => Ok(b.to_string()),
+        _ => Err(invalid()),
+    };
+
+    let mut build_args = IndexMap::new();
+    match args {
+        None => {}
+        Some(Value::Mapping(m)) => {
+            for (k, v) in m {
+                build_args.insert(scalar(k)?, scalar(v)?);
+            }
+        }
+        Some(Value::Sequence(seq)) => {
+            for v in seq {
+                let arg = scalar(v)?;
+                let (k, v) = arg.split_once('=').ok_or_else(invalid)?;
+                build_args.insert(k.to_string(), v.to_string());
+            }
+        }
+        Some(_) => return Err(invalid()),
+    }
+    Ok(build_args)
+}
+
+// テンプレートを読み込む
+fn read_template(path: &Path) -> Result<String, Error> {
+    let file = fs::File::open(path).map_err(|err| Error::Io {
+        path: Some(path.to_path_buf()),
+        source: err,
+    })?;
+    let mut reader = io::BufReader::new(file);
+    let mut contents = String::new();
+    reader
+        .read_to_string(&mut contents)
+        .map_err(|err| Error::Io {
+            path: Some(path.to_path_buf()),
+            source: err,
+        })?;
+    Ok(contents)
+}
+
+// プロジェクトの設定をサービスの設定に重ねる
+fn apply_project_config(service: &mut Service, config: &ProjectConfig) {
+    // マウントとポートはテンプレートのものに追加する
+    let volumes = service.volumes.get_or_insert_with(Vec::new);
+    volumes.extend(config.mounts.iter().cloned().map(Value::String));
+
+    if !config.ports.is_empty() {
+        let ports = service
+            .other
+            .entry("ports".into())
+            .or_insert_with(|| Value::Sequence(Vec::new()));
+        if let Value::Sequence(ports) = ports {
+            ports.extend(config.ports.iter().cloned().map(Value::String));
+        }
+    }
+
+    // 環境変数はテンプレートの書式 (リストかマップ) に合わせて追加する
+    if !config.environment.is_empty() {
+        let environment = service
+            .other
+            .entry("environment".into())
+            .or_insert_with(|| Value::Mapping(Mapping::new()));
+        match environment {
+            Value::Sequence(seq) => {
+                for (k, v) in &config.environment {
+                    seq.retain(|e| {
+                        e.as_str()
+                            .is_none_or(|e| e.split('=').next() != Some(k.as_str()))
+                    });
+                    seq.push(Value::String(format!("{k}={v}")));
+                }
+            }
+            Value::Mapping(map) => {
+                for (k, v) in &config.environment {
+                    map.insert(Value::String(k.clone()), Value::String(v.clone()));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // リソース制限はテンプレートの値を上書きする
+    if let Some(cpus) = config.resources.cpus {
+        service.other.insert("cpus".into(), Value::from(cpus));
+    }
+    if let Some(memory) = &config.resources.memory {
+        service
+            .other
+            .insert("mem_limit".into(), Value::String(memory.clone()));
+    }
+    if let Some(pids) = config.resources.pids {
+        service.other.insert("pids_limit".into(), Value::from(pids));
+    }
+}
+
+// セキュリティプロファイルに合わせて主サービスの権限を設定する (テンプレートの設定は使わない)
+fn apply_security(service: &mut Service, profile: SecurityProfile) {
+    for key in ["privileged", "cap_add", "cap_drop", "security_opt"] {
+        service.other.shift_remove(key);
+    }
+
+    let strings =
+        |values: &[&str]| Value::Sequence(values.iter().map(|v| Value::from(*v)).collect());
+    match profile {
+        SecurityProfile::Minimal => {
+            service
+                .other
+                .insert("security_opt".into(), strings(&["no-new-privileges:true"]));
+        }
+        SecurityProfile::Debug => {
+            service
+                .other
+                .insert("cap_add".into(), strings(&["SYS_PTRACE"]));
+            service
+                .other
+                .insert("security_opt".into(), strings(&["seccomp=unconfined"]));
+        }
+        SecurityProfile::Privileged => {
+            service.other.insert("privileged".into(), Value::Bool(true));
+            service
+                .other
+                .insert("cap_add".into(), strings(&["SYS_PTRACE"]));
+            service
+                .other
+                .insert("security_opt".into(), strings(&["seccomp=unconfined"]));
+        }
+    }
+}
+
+// ネットワークの種類に合わせて主サービスのnetwork_modeとportsを設定する
+// ネットワークが指定されていない場合は呼ばず、テンプレートの設定をそのまま使う
+// ブリッジの場合はnetwork_modeを指定せず、composeが環境ごとに作るネットワークにつなぐ
+fn apply_network(service: &mut Service, mode: NetworkMode) {
+    match mode {
+        NetworkMode::Host | NetworkMode::Disabled => {
+            service
+                .other
+                .insert("network_mode".into(), Value::from(mode.as_str()));
+            // ホストのネットワークやネットワークなしではポートを公開できない
+            service.other.shift_remove("ports");
+        }
+        NetworkMode::Bridge => {
+            service.other.shift_remove("network_mode");
+        }
+    }
+}
+
+// 公開するポートにホストのポートを割り当てる
+// テンプレートはすべての環境で共有されるので、テンプレートのポートにはport_rangeの中から空いているものを割り当てる
+// .roxy.tomlでホストのポートまで指定されたものはそのまま使い、他の環境などが使っている場合はエラーにする
+// 作り直す場合は、以前割り当てたポートが他の環境に使われていなければ同じものを使う
+// in_useは他の環境が使っているポートで、ports_in_useで集める
+// 空いているかを確かめてからコンテナが待ち受けるまでのあいだに他の環境に取られないよう、
+// 呼び出し側(init、rebuild)はlock_envsで環境の作成を排他している
+// roxy以外のプロセスとの競合は防げないので、その場合はコンテナの起動に失敗する
+fn assign_host_ports(
+    compose: &mut Compose,
+    env_spec: &EnvSpec,
+    previous: &[PortMapping],
+    in_use: &HashMap<(u16, String), Uuid>,
+) -> Result<(), Error> {
+    let (start, end) = compose.extension()?.port_range()?;
+
+    // ホストのポートが指定されたものを先に確保し、残りに割り当てる
+    let mut specs = Vec::new();
+    let mut assigned = HashSet::new();
+    for (name, service) in &compose.services {
+        if !service.publishes_ports() {
+            continue;
+        }
+        let Some(Value::Sequence(ports)) = service.other.get("ports") else {
+            continue;
+        };
+        for (i, port) in ports.iter().enumerate() {
+            let mut spec = PortSpec::parse(port)?;
+            let explicit = port
+                .as_str()
+                .is_some_and(|port| env_spec.config.ports.iter().any(|p| p == port));
+            if !explicit {
+                spec.host_port = None;
+            }
+            if let Some(host_port) = spec.host_port {
+                let key = (host_port, spec.protocol.clone());
+                let previously_used = previous
+                    .iter()
+                    .any(|p| p.host_port == host_port && p.protocol == spec.protocol);
+                let owner = if let Some(uuid) = in_use.get(&key) {
+                    Some(format!("environment {uuid}"))
+                } else if !assigned.insert(key) {
+                    Some(format!("another port of {}", env_spec.project_name))
+                } else if !previously_used
+                    && !is_port_free(spec.host_ip.as_deref(), host_port, &spec.protocol)
+                {
+                    Some("another process".into())
+                } else {
+                    None
+                };
+                if let Some(owner) = owner {
+                    return Err(Error::PortConflict {
+                        port: host_port,
+                        protocol: spec.protocol,
+                        owner,
+                    });
+                }
+            }
+            specs.push((name.clone(), i, spec));
+        }
+    }
+
+    for (name, i, spec) in &mut specs {
+        if spec.host_port.is_some() {
+            continue;
+        }
+        let available = |port: u16| {
+            let key = (port, spec.protocol.clone());
+            !in_use.contains_key(&key) && !assigned.contains(&key)
+        };
+
+        // 以前と同じポートは自分のコンテナが使っている可能性があるので、待ち受けられるかは確かめない
+        let reused = previous
+            .iter()
+            .find(|p| {
+                p.service == *name
+                    && p.container_port == spec.container_port
+                    && p.protocol == spec.protocol
+            })
+            .map(|p| p.host_port)
+            .filter(|port| available(*port));
+        let host_port = match reused {
+            Some(port) => port,
+            None => (start..=end)
+                .find(|port| {
+                    available(*port) && is_port_free(spec.host_ip.as_deref(), *port, &spec.protocol)
+                })
+                .ok_or(Error::PortsExhausted { start, end })?,
+        };
+        assigned.insert((host_port, spec.protocol.clone()));
+        spec.host_port = Some(host_port);
+
+        if let Some(Value::Sequence(ports)) = compose.services[name.as_str()].other.get_mut("ports")
+        {
+            ports[*i] = spec.to_value();
+        }
+    }
+
+    Ok(())
+}
+
+// 他の環境の設定ディレクトリに書き出されたホストのポートと、それを使っている環境のuuidを返す
+// 停止している環境のポートも、再開したときに衝突しないよう使用中とみなす
+fn ports_in_use(uuid: Uuid) -> HashMap<(u16, String), Uuid> {
+    let mut in_use = HashMap::new();
+    for (other, path) in config_dirs().unwrap_or_default() {
+        if other == uuid {
+            continue;
+        }
+        let Ok(ports) = load_compose(&path).and_then(|compose| published_ports(&compose)) else {
+            continue;
+        };
+        for port in ports {
+            in_use.insert((port.host_port, port.protocol), other);
+        }
+    }
+    in_use
+}
+
+// ホストのポートで待ち受けられるかどうか (アドレスが解釈できない場合は空いているとみなす)
+fn is_port_free(host_ip: Option<&str>, port: u16, protocol: &str) -> bool {
+    let ip = match host_ip {
+        Some(ip) => match ip.trim_start_matches('[').trim_end_matches(']').parse() {
+            Ok(ip) => ip,
+            Err(_) => return true,
+        },
+        None => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+    };
+    match protocol {
+        "udp" => UdpSocket::bind((ip, port)).is_ok(),
+        _ => TcpListener::bind((ip, port)).is_ok(),
+    }
+}
+
+// 主サービスを実行するユーザーを設定する
+// "host"の場合はワークスペースに作られるファイルがホストのユーザーのものになるよう同じUID/GIDで実行し、
+// テンプレートが同じUID/GIDのユーザーを用意できるようにビルド引数HOST_UIDとHOST_GIDを渡す
+fn apply_user(service: &mut Service, user: &str, runtime: RuntimeKind) {
+    if user != HOST_USER {
+        service
+            .other
+            .insert("user".into(), Value::String(user.to_string()));
+        return;
+    }
+
+    let (uid, gid) = (util::host_uid(), util::host_gid());
+    service
+        .other
+        .insert("user".into(), Value::String(format!("{uid}:{gid}")));
+
+    // rootlessのPodmanはコンテナ内のUIDをサブUIDに対応させるので、ホストと同じUIDに対応させる
+    if runtime == RuntimeKind::Podman && uid != 0 {
+        service
+            .other
+            .insert("userns_mode".into(), Value::String("keep-id".into()));
+    }
+
+    let Some(build) = service.other.get_mut("build") else {
+        return;
+    };
+    // build: <context> の形式は引数を渡せるようにマップの形式に直す
+    if let Value::String(context) = build {
+        let mut mapping = Mapping::new();
+        mapping.insert("context".into(), Value::String(context.clone()));
+        *build = Value::Mapping(mapping);
+    }
+    let Value::Mapping(build) = build else {
+        return;
+    };
+    let build_args = [("HOST_UID", uid), ("HOST_GID", gid)];
+    match build
+        .entry("args".into())
+        .or_insert_with(|| Value::Mapping(Mapping::new()))
+    {
+        Value::Mapping(args) => {
+            for (name, value) in build_args {
+                args.insert(name.into(), Value::String(value.to_string()));
+            }
+        }
+        Value::Sequence(args) => {
+            for (name, value) in build_args {
+                args.push(Value::String(format!("{name}={value}")));
+            }
+        }
+        _ => {}
+    }
+}
+
+// 設定ディレクトリに書き出したcompose.ymlを読み込む
+pub fn load_compose(config_path: &Path) -> Result<Compose, Error> {
+    let compose_path = config_path.join(COMPOSE_NAME);
+    let contents = fs::read_to_string(&compose_path).map_err(|err| Error::Io {
+        path: Some(compose_path),
+        source: err,
+    })?;
+    serde_yaml::from_str(&contents).map_err(Error::YamlSer)
+}
+
+// 環境に入るときに使うシェルを決める
+// 指定されたシェル、テンプレートで設定されたシェル、コンテナ内で見つかったシェルの順に優先する
+// テンプレートで設定されたシェルは主サービスに入る場合にのみ使う
+pub fn resolve_shell(
+    uuid: Uuid,
+    shell: Option<&str>,
+    primary: bool,
+    mut probe: impl FnMut(&[String]) -> Result<bool, Error>,
+) -> Result<String, Error> {
+    if let Some(shell) = shell {
+        return Ok(shell.to_string());
+    }
+
+    // 設定ディレクトリが消えている場合もコンテナ内を探せば入れるので、読み込みの失敗は無視する
+    let template_shell = load_compose(&config_dir_path(uuid))
+        .and_then(|compose| compose.extension())
+        .ok()
+        .and_then(|extension| extension.shell);
+    if primary && let Some(shell) = template_shell {
+        return Ok(shell);
+    }
+
+    for candidate in SHELL_CANDIDATES {
+        let argv = [candidate.to_string(), "-c".into(), "exit 0".into()];
+        if probe(&argv)? {
+            return Ok(candidate.to_string());
+        }
+    }
+
+    Err(Error::NotFound {
+        what: "shell in the container",
+    })
+}
+
+// コンテナエンジンが報告する状態 (State.Status) を変換する
+// 再起動中のものは動いているものとして、それ以外 (created, exited, deadなど) は止まっているものとして扱う
+pub fn status_from_str(status: &str) -> ContainerStatus {
+    match status {
+        "running" | "restarting" => ContainerStatus::Running,
+        "paused" => ContainerStatus::Paused,
+        _ => ContainerStatus::Exited,
+    }
+}
+
+// 設定ディレクトリを環境のuuidとともにすべて返す
+pub fn config_dirs() -> Result<Vec<(Uuid, PathBuf)>, Error> {
+    list_config_dirs(&config_root_path())
+}
+
+// rootの直下にある設定ディレクトリを環境のuuidとともにすべて返す
+fn list_config_dirs(root: &Path) -> Result<Vec<(Uuid, PathBuf)>, Error> {
+    let entries = match fs::read_dir(root) {
+        Ok(e) => e,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => {
+            return Err(Error::Io {
+                path: Some(root.to_path_buf()),
+                source: err,
+            });
+        }
+    };
+
+    let mut dirs = Vec::new();
+    for entry in entries {
+        let entry = entry.map_err(|err| Error::Io {
+            path: Some(root.to_path_buf()),
+            source: err,
+        })?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        // uuidとして解釈できないものはroxyが作成したものではないとみなす
+        if let Some(uuid) = name.strip_prefix(CONFIG_DIR_PREFIX)
+            && let Ok(uuid) = Uuid::parse_str(uuid)
+            && entry.path().is_dir()
+        {
+            dirs.push((uuid, entry.path()));
+        }
+    }
+
+    Ok(dirs)
+}
+
+// 設定ディレクトリを削除する (既に存在しない場合は何もしない)
+pub fn remove_config_dir(uuid: Uuid) -> Result<(), Error> {
+    remove_dir_if_exists(&config_dir_path(uuid))
+}
+
+// prepare_config_dirで展開した設定ディレクトリで、環境の設定ディレクトリを置き換えてそのパスを返す
+// 以前の設定ディレクトリは退避してから削除するので、置き換えに失敗した場合は元に戻す
+pub fn commit_config_dir(uuid: Uuid) -> Result<PathBuf, Error> {
+    let staging_path = staging_dir_path(uuid, "new");
+    let old_path = staging_dir_path(uuid, "old");
+    let config_path = config_dir_path(uuid);
+
+    remove_dir_if_exists(&old_path)?;
+    let had_old = match fs::rename(&config_path, &old_path) {
+        Ok(()) => true,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => false,
+        Err(err) => {
+            return Err(Error::Io {
+                path: Some(config_path),
+                source: err,
+            });
+        }
+    };
+
+    if let Err(err) = fs::rename(&staging_path, &config_path) {
+        if had_old {
+            let _ = fs::rename(&old_path, &config_path);
+        }
+        return Err(Error::Io {
+            path: Some(staging_path),
+            source: err,
+        });
+    }
+
+    if had_old {
+        remove_dir_if_exists(&old_path)?;
+    }
+    Ok(config_path)
+}
+
+// prepare_config_dirで展開した設定ディレクトリを捨てる (環境の設定ディレクトリはそのまま残る)
+pub fn discard_config_dir(uuid: Uuid) -> Result<(), Error> {
+    remove_dir_if_exists(&staging_dir_path(uuid, "new"))
+}
+
+fn remove_dir_if_exists(path: &Path) -> Result<(), Error> {
+    match fs::remove_dir_all(path) {
+        Ok(()) => Ok(()),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(err) => Err(Error::Io {
+            path: Some(path.to_path_buf()),
+            source: err,
+        }),
+    }
+}
+
+// 主サービスのコンテナidと、サービス名とコンテナidの組
+type Containers = (ContainerId, IndexMap<String, ContainerId>);
+
+// composeサブコマンドを持つコンテナエンジンのCLI (docker, podman) を操作する
+pub struct ComposeCli {
+    program: &'static str,
+}
+
+impl ComposeCli {
+    pub fn new(program: &'static str) -> Self {
+        Self { program }
+    }
+
+    fn compose_file_arg(config_path: &Path) -> String {
+        config_path.join(COMPOSE_NAME).display().to_string()
+    }
+
+    // compose up -dを実行する
+    // buildが指定された場合は--buildでbuildが残っているサービスのイメージをビルドし直す
+    // recreateが指定された場合は既存のコンテナを作り直す
+    pub fn up(&self, config_path: &Path, build: bool, recreate: bool) -> Result<(), Error> {
+        let cmd = format!("{} compose", self.program);
+
+        let status = Command::new(self.program)
+            .args([
+                "compose",
+                "-f",
+                &Self::compose_file_arg(config_path),
+                "up",
+                "-d",
+            ])
+            .args(build.then_some("--build"))
+            .args(recreate.then_some("--force-recreate"))
+            .stdin(Stdio::inherit())
+            .stdout(Stdio::inherit())
+            .stderr(Stdio::inherit())
+            .status()
+            .map_err(|err| Error::Command {
+                cmd: cmd.clone(),
+                status: None,
+                err: err.to_string(),
+            })?;
+
+        if !status.success() {
+            return Err(Error::Command {
+                cmd,
+                status: status.code(),
+                err: String::new(),
+            });
+        }
+
+        Ok(())
+    }
+
+    // イメージがなければビルドする (同じタグのイメージは他の環境がビルドしたものを使う)
+    pub fn ensure_image(&self, image: &ImageBuild) -> Result<(), Error> {
+        let exists = Command::new(self.program)
+            .args(["image", "inspect", &image.tag])
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .map_err(|err| Error::Command {
+                cmd: format!("{} image inspect", self.program),
+                status: None,
+                err: err.to_string(),
+            })?
+            .success();
+        if exists {
+            info!("Using the existing image {}", image.tag);
+            return Ok(());
+        }
+
+        let cmd = format!("{} build", self.program);
+        let dockerfile = image.context.join(&image.dockerfile);
+        let build_args = image
+            .args
+            .iter()
+            .flat_map(|(k, v)| ["--build-arg".to_string(), format!("{k}={v}")]);
+
+        let status = Command::new(self.program)
+            .args(["build", "-t", &image.tag, "-f"])
+            .arg(&dockerfile)
+            .args(build_args)
+            .arg(&image.context)
+            .stdin(Stdio::inherit())
+            .stdout(Stdio::inherit())
+            .stderr(Stdio::inherit())
+            .status()
+            .map_err(|err| Error::Command {
+                cmd: cmd.clone(),
+                status: None,
+                err: err.to_string(),
+            })?;
+
+        if !status.success() {
+            return Err(Error::Command {
+                cmd,
+                status: status.code(),
+                err: String::new(),
+            });
+        }
+
+        Ok(())
+    }
+
+    // 起動したサービスのコンテナidを取得し、主サービスのidとサービスごとのidを返す
+    pub fn containers(&self, config_path: &Path) -> Result<Containers, Error> {
+        self.find_containers(config_path)?.ok_or(Error::NotFound {
+            what: "container id from compose ps",
+        })
+    }
+
+    // compose projectのコンテナを探す (いずれかのサービスのコンテナが見つからない場合はNone)
+    pub fn find_containers(&self, config_path: &Path) -> Result<Option<Containers>, Error> {
+        let compose = load_compose(config_path)?;
+        let primary = compose.primary_service()?;
+
+        let mut services = IndexMap::new();
+        for service_name in compose.services.keys() {
+            let Some(container_id) = self.ps(config_path, service_name)? else {
+                return Ok(None);
+            };
+            services.insert(service_name.clone(), container_id);
+        }
+
+        Ok(Some((services[&primary].clone(), services)))
+    }
+
+    // compose ps -a -qでサービスのコンテナidを取得する (停止しているコンテナも含む)
+    fn ps(&self, config_path: &Path, service_name: &str) -> Result<Option<ContainerId>, Error> {
+        let cmd = format!("{} compose ps", self.program);
+
+        let output = Command::new(self.program)
+            .args([
+                "compose",
+                "-f",
+                &Self::compose_file_arg(config_path),
+                "ps",
+                "-a",
+                "-q",
+                service_name,
+            ])
+            .output()
+            .map_err(|err| Error::Command {
+                cmd: cmd.clone(),
+                status: None,
+                err: err.to_string(),
+            })?;
+        if !output.status.success() {
+            return Err(Error::Command {
+                cmd,
+                status: output.status.code(),
+                err: String::from_utf8_lossy(&output.stderr).into(),
+            });
+        }
+
+        // サービスごとにコンテナは1つしか存在しないと仮定して、先頭のidだけを取得する
+        Ok(String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .map(str::trim)
+            .find(|l| !l.is_empty())
+            .map(ContainerId::from_str))
+    }
+
+    // コンテナ内でコマンドを対話的に実行する (このプロセスは置き換えられる)
+    pub fn exec_interactive(&self, container_id: &ContainerId, argv: &[&str]) -> Error {
+        let err = Command::new(self.program)
+            .args(["exec", "-it", &container_id.to_string()])
+            .args(argv)
+            .stdin(Stdio::inherit())
+            .stdout(Stdio::inherit())
+            .stderr(Stdio::inherit())
+            .exec();
+
+        Error::Command {
+            cmd: format!("{} exec", self.program),
+            status: None,
+            err: err.to_string(),
+        }
+    }
+
+    // コンテナ内でコマンドを実行し、その終了コードを返す
+    pub fn exec(
+        &self,
+        container_id: &ContainerId,
+        argv: &[String],
+        tty: bool,
+    ) -> Result<i32, Error> {
+        let flags = if tty { "-it" } else { "-i" };
+
+        let status = Command::new(self.program)
+            .args(["exec", flags, &container_id.to_string()])
+            .args(argv)
+            .stdin(Stdio::inherit())
+            .stdout(Stdio::inherit())
+            .stderr(Stdio::inherit())
+            .status()
+            .map_err(|err| Error::Command {
+                cmd: format!("{} exec", self.program),
+                status: None,
+                err: err.to_string(),
+            })?;
+
+        // シグナルで終了した場合はシェルと同じく128+シグナル番号を終了コードとする
+        Ok(status
+            .code()
+            .or_else(|| status.signal().map(|sig| 128 + sig))
+            .unwrap_or(1))
+    }
+
+    // 入出力を接続せずにコンテナ内でコマンドを実行し、成功したかどうかを返す
+    pub fn probe(&self, container_id: &ContainerId, argv: &[String]) -> Result<bool, Error> {
+        let status = Command::new(self.program)
+            .args(["exec", &container_id.to_string()])
+            .args(argv)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .map_err(|err| Error::Command {
+                cmd: format!("{} exec", self.program),
+                status: None,
+                err: err.to_string(),
+            })?;
+
+        Ok(status.success())
+    }
+
+    // コンテナの状態を調べる
+    pub fn inspect(&self, container_id: &ContainerId) -> Result<ContainerStatus, Error> {
+        let cmd = format!("{} inspect", self.program);
+
+        let output = Command::new(self.program)
+            .args([
+                "inspect",
+                "--type",
+                "container",
+                "--format",
+                "{{.State.Status}}",
+                &container_id.to_string(),
+            ])
+            .output()
+            .map_err(|err| Error::Command {
+                cmd: cmd.clone(),
+                status: None,
+                err: err.to_string(),
+            })?;
+
+        if !output.status.success() {
+            // コンテナが存在しない場合はエラーではなく状態として返す
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            if stderr.to_lowercase().contains("no such") {
+                return Ok(ContainerStatus::Missing);
+            }
+            return Err(Error::Command {
+                cmd,
+                status: output.status.code(),
+                err: stderr.into(),
+            });
+        }
+
+        Ok(status_from_str(
+            String::from_utf8_lossy(&output.stdout).trim(),
+        ))
+    }
+
+    // composeで作成されたコンテナのうち、roxyの設定ディレクトリから作成されたものをすべて返す
+    pub fn project_containers(&self) -> Result<Vec<(Uuid, ContainerId)>, Error> {
+        let label = "com.docker.compose.project";
+
+        let output = self.output(&["ps", "-a", "-q", "--filter", &format!("label={label}")])?;
+        let ids = output.split_whitespace().collect::<Vec<_>>();
+        if ids.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        // compose projectの名前は設定ディレクトリの名前になっている
+        let format = format!("{{{{.Id}}}} {{{{index .Config.Labels \"{label}\"}}}}");
+        let mut args = vec!["inspect", "--type", "container", "--format", &format];
+        args.extend(ids);
+        let output = self.output(&args)?;
+
+        Ok(output
+            .lines()
+            .filter_map(|line| {
+                let (id, project) = line.trim().split_once(' ')?;
+                let uuid = Uuid::parse_str(project.strip_prefix(CONFIG_DIR_PREFIX)?).ok()?;
+                Some((uuid, ContainerId::from_str(id)))
+            })
+            .collect())
+    }
+
+    // コマンドを実行し、その標準出力を返す
+    fn output(&self, args: &[&str]) -> Result<String, Error> {
+        let cmd = format!("{} {}", self.program, args.first().unwrap_or(&""));
+
+        let output = Command::new(self.program)
+            .args(args)
+            .output()
+            .map_err(|err| Error::Command {
+                cmd: cmd.clone(),
+                status: None,
+                err: err.to_string(),
+            })?;
+
+        if !output.status.success() {
+            return Err(Error::Command {
+                cmd,
+                status: output.status.code(),
+                err: String::from_utf8_lossy(&output.stderr).into(),
+            });
+        }
+
+        Ok(String::from_utf8_lossy(&output.stdout).into())
+    }
+
+    // 引数を受け取るサブコマンドを実行する
+    fn run(&self, sub_command: &str, container_id: &ContainerId) -> Result<(), Error> {
+        let cmd = format!("{} {}", self.program, sub_command);
+
+        let output = Command::new(self.program)
+            .args([sub_command, &container_id.to_string()])
+            .output()
+            .map_err(|err| Error::Command {
+                cmd: cmd.clone(),
+                status: None,
+                err: err.to_string(),
+            })?;
+
+        if !output.status.success() {
+            return Err(Error::Command {
+                cmd,
+                status: output.status.code(),
+                err: String::from_utf8_lossy(&output.stderr).into(),
+            });
+        }
+
+        Ok(())
+    }
+
+    pub fn stop(&self, container_id: &ContainerId) -> Result<(), Error> {
+        self.run("stop", container_id)
+    }
+
+    pub fn start(&self, container_id: &ContainerId) -> Result<(), Error> {
+        self.run("start", container_id)
+    }
+
+    // コンテナのイメージのidを返す
+    pub fn image_id(&self, container_id: &ContainerId) -> Result<String, Error> {
+        let output = self.output(&[
+            "inspect",
+            "--type",
+            "container",
+            "--format",
+            "{{.Image}}",
+            &container_id.to_string(),
+        ])?;
+        Ok(output.trim().to_string())
+    }
+
+    // 起動しているかどうかにかかわらずコンテナを削除する
+    pub fn remove(&self, container_id: &ContainerId) -> Result<(), Error> {
+        self.output(&["rm", "-f", &container_id.to_string()])
+            .map(|_| ())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn env_spec() -> EnvSpec {
+        EnvSpec {
+            uuid: Uuid::new_v4(),
+            project_path: PathBuf::from("/tmp/project"),
+            project_name: "project".into(),
+            config: ProjectConfig::default(),
+            template_hash: None,
+        }
+    }
+
+    fn compose_with_ports(port_range: &str) -> Compose {
+        serde_yaml::from_str(&format!(
+            "services:\n  app:\n    ports: [\"8080\"]\nx-roxy:\n  port_range: \"{port_range}\"\n"
+        ))
+        .unwrap()
+    }
+
+    fn host_port(compose: &Compose) -> u16 {
+        published_ports(compose).unwrap()[0].host_port
+    }
+
+    fn mapping(host_port: u16) -> PortMapping {
+        PortMapping {
+            service: "app".into(),
+            container_port: 8080,
+            protocol: "tcp".into(),
+            host_ip: None,
+            host_port,
+        }
+    }
+
+    #[test]
+    fn port_range_parses_start_and_end() {
+        let range = |range: Option<&str>| {
+            Extension {
+                port_range: range.map(String::from),
+                ..Extension::default()
+            }
+            .port_range()
+        };
+        assert_eq!(range(None).unwrap(), DEFAULT_PORT_RANGE);
+        assert_eq!(range(Some("30000-30010")).unwrap(), (30000, 30010));
+        assert_eq!(range(Some(" 30000 - 30000 ")).unwrap(), (30000, 30000));
+        for invalid in [
+            "",
+            "30000",
+            "30000-",
+            "a-b",
+            "0-10",
+            "30010-30000",
+            "1-65536",
+        ] {
+            assert!(
+                matches!(
+                    range(Some(invalid)),
+                    Err(Error::InvalidComposeConfig { .. })
+                ),
+                "{invalid:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn assign_host_ports_reuses_previous_port() {
+        // 以前のポートは自分のコンテナが待ち受けていても使い続ける
+        let listener = TcpListener::bind((Ipv4Addr::UNSPECIFIED, 0)).unwrap();
+        let previous = listener.local_addr().unwrap().port();
+
+        let mut compose = compose_with_ports("41000-41099");
+        assign_host_ports(
+            &mut compose,
+            &env_spec(),
+            &[mapping(previous)],
+            &HashMap::new(),
+        )
+        .unwrap();
+        assert_eq!(host_port(&compose), previous);
+    }
+
+    #[test]
+    fn assign_host_ports_skips_previous_port_taken_by_other_env() {
+        let mut compose = compose_with_ports("41000-41099");
+        let in_use = HashMap::from([((41000, "tcp".to_string()), Uuid::new_v4())]);
+        assign_host_ports(&mut compose, &env_spec(), &[mapping(41000)], &in_use).unwrap();
+        let port = host_port(&compose);
+        assert_ne!(port, 41000);
+        assert!((41001..=41099).contains(&port));
+    }
+
+    #[test]
+    fn assign_host_ports_fails_when_range_is_exhausted() {
+        let mut compose = compose_with_ports("41000-41001");
+        let other = Uuid::new_v4();
+        let in_use = HashMap::from([
+            ((41000, "tcp".to_string()), other),
+            ((41001, "tcp".to_string()), other),
+        ]);
+        let err = assign_host_ports(&mut compose, &env_spec(), &[], &in_use).unwrap_err();
+        assert!(matches!(
+            err,
+            Error::PortsExhausted {
+                start: 41000,
+                end: 41001
+            }
+        ));
+    }
+
+    #[test]
+    fn share_images_leaves_build_only_for_outside_contexts() {
+        let dir = env::temp_dir().join(format!("roxy-share-images-{}", Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("dockerfile"), "FROM scratch\n").unwrap();
+
+        let mut compose: Compose = serde_yaml::from_str(
+            "services:\n  app:\n    build:\n      context: .\n      dockerfile: dockerfile\n",
+        )
+        .unwrap();
+        let images = share_images(&mut compose, &dir).unwrap();
+        assert_eq!(images.len(), 1);
+        assert!(!compose.has_builds());
+
+        let mut compose: Compose =
+            serde_yaml::from_str("services:\n  app:\n    build: ../other\n").unwrap();
+        assert!(share_images(&mut compose, &dir).unwrap().is_empty());
+        assert!(compose.has_builds());
+
+        // targetはタグに反映できないので、targetだけが異なるテンプレートが同じイメージを使わないようにする
+        let mut compose: Compose = serde_yaml::from_str(
+            "services:\n  app:\n    build:\n      context: .\n      dockerfile: dockerfile\n      target: dev\n",
+        )
+        .unwrap();
+        assert!(share_images(&mut compose, &dir).unwrap().is_empty());
+        assert!(compose.has_builds());
+        assert!(!compose.services["app"].other.contains_key("image"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn status_from_str_maps_engine_states() {
+        assert_eq!(status_from_str("running"), ContainerStatus::Running);
+        assert_eq!(status_from_str("restarting"), ContainerStatus::Running);
+        assert_eq!(status_from_str("paused"), ContainerStatus::Paused);
+        for status in ["created", "exited", "dead", "removing", ""] {
+            assert_eq!(status_from_str(status), ContainerStatus::Exited);
+        }
+    }
+}
@@ -0,0 +1,187 @@
+use std::path::PathBuf;
+
+use uuid::Uuid;
+
+use crate::domain::repo::{
+    ContainerId, ContainerInfo, ContainerStatus, EnvRecord, EnvSpec, EnvState, Error, PortMapping,
+    Runtime, RuntimeContainer, RuntimeKind, SharedResources,
+};
+
+use super::compose::{self, ComposeCli};
+
+// composeサブコマンドを持つCLI (docker, podman) を使うランタイム
+// どちらもCLIの使い方は同じなので、実行するプログラムだけを切り替える
+pub struct ComposeCliForContainerRuntime {
+    cli: ComposeCli,
+    kind: RuntimeKind,
+}
+
+impl ComposeCliForContainerRuntime {
+    // kindはDockerかPodmanで、同じ名前のプログラムを実行する
+    pub fn new(kind: RuntimeKind) -> Self {
+        Self {
+            cli: ComposeCli::new(kind.as_str()),
+            kind,
+        }
+    }
+
+    // テンプレートから設定ディレクトリを作成し、コンテナを起動する
+    fn create(
+        &mut self,
+        shared_resources: &SharedResources,
+        env_spec: &EnvSpec,
+        recreate: bool,
+    ) -> Result<ContainerInfo, Error> {
+        // テンプレートから設定ディレクトリを作成する
+        let (_, images) = compose::prepare_config_dir(shared_resources, env_spec, self.kind)?;
+
+        // 環境のあいだで共有するイメージを用意する
+        // ビルドに失敗した場合は、作り直す前の設定ディレクトリを残しておく
+        for image in &images {
+            if let Err(err) = self.cli.ensure_image(image) {
+                let _ = compose::discard_config_dir(env_spec.uuid);
+                return Err(err);
+            }
+        }
+        let config_path = compose::commit_config_dir(env_spec.uuid)?;
+
+        // 共有するイメージは用意してあるので、設定ディレクトリの外をコンテキストとするサービスがある場合にのみビルドする
+        let compose = compose::load_compose(&config_path)?;
+        self.cli.up(&config_path, compose.has_builds(), recreate)?;
+
+        // 各サービスのコンテナidを取得する
+        let (container_id, services) = self.cli.containers(&config_path)?;
+        let image_id = self.cli.image_id(&container_id)?;
+        let ports = compose::published_ports(&compose)?;
+
+        Ok(ContainerInfo {
+            container_id,
+            services,
+            image_id: Some(image_id),
+            runtime: self.kind,
+            state: EnvState::Running,
+            ports,
+        })
+    }
+}
+
+impl Runtime for ComposeCliForContainerRuntime {
+    fn init(
+        &mut self,
+        shared_resources: &SharedResources,
+        env_spec: &EnvSpec,
+    ) -> Result<ContainerInfo, Error> {
+        self.create(shared_resources, env_spec, false)
+    }
+
+    fn rebuild(
+        &mut self,
+        shared_resources: &SharedResources,
+        record: &EnvRecord,
+    ) -> Result<ContainerInfo, Error> {
+        // 同じcompose projectのコンテナを作り直す
+        self.create(shared_resources, &record.spec, true)
+    }
+
+    fn enter(
+        &mut self,
+        record: &EnvRecord,
+        service: Option<&str>,
+        shell: Option<&str>,
+    ) -> Result<(), Error> {
+        let container_id = record.container_info.container(service)?;
+        let primary = container_id == &record.container_info.container_id;
+        let shell = compose::resolve_shell(record.spec.uuid, shell, primary, |argv| {
+            self.cli.probe(container_id, argv)
+        })?;
+
+        Err(self.cli.exec_interactive(container_id, &[&shell]))
+    }
+
+    fn exec(
+        &mut self,
+        record: &EnvRecord,
+        service: Option<&str>,
+        argv: &[String],
+        tty: bool,
+    ) -> Result<i32, Error> {
+        let container_id = record.container_info.container(service)?;
+        self.cli.exec(container_id, argv, tty)
+    }
+
+    fn stop(&mut self, record: &EnvRecord) -> Result<(), Error> {
+        for container_id in record.container_info.container_ids() {
+            self.cli.stop(container_id)?;
+        }
+        Ok(())
+    }
+
+    fn start(&mut self, record: &EnvRecord) -> Result<(), Error> {
+        for container_id in record.container_info.container_ids() {
+            self.cli.start(container_id)?;
+        }
+        Ok(())
+    }
+
+    fn kill(&mut self, record: &EnvRecord) -> Result<(), Error> {
+        // 記録された状態は実際のコンテナの状態と食い違うことがあるので、状態によらず強制的に削除する
+        for container_id in record.container_info.container_ids() {
+            self.cli.remove(container_id)?;
+        }
+
+        // 設定ディレクトリを削除する
+        compose::remove_config_dir(record.spec.uuid)
+    }
+
+    fn inspect(
+        &mut self,
+        _record: &EnvRecord,
+        container_id: &ContainerId,
+    ) -> Result<ContainerStatus, Error> {
+        self.cli.inspect(container_id)
+    }
+
+    fn refresh(&mut self, record: &EnvRecord) -> Result<Option<ContainerInfo>, Error> {
+        // 設定ディレクトリが残っていなければcompose projectを特定できない
+        let config_path = compose::config_dir_path(record.spec.uuid);
+        if !config_path.join(compose::COMPOSE_NAME).is_file() {
+            return Ok(None);
+        }
+
+        let containers = self.cli.find_containers(&config_path)?;
+        Ok(containers.map(|(container_id, services)| ContainerInfo {
+            container_id,
+            services,
+            ..record.container_info.clone()
+        }))
+    }
+
+    fn published_ports(&mut self, record: &EnvRecord) -> Result<Vec<PortMapping>, Error> {
+        compose::load_compose(&compose::config_dir_path(record.spec.uuid))
+            .and_then(|compose| compose::published_ports(&compose))
+    }
+
+    fn containers(&mut self) -> Result<Vec<RuntimeContainer>, Error> {
+        let containers = self.cli.project_containers()?;
+        Ok(containers
+            .into_iter()
+            .map(|(uuid, container_id)| RuntimeContainer {
+                uuid,
+                container_id,
+                runtime: self.kind,
+            })
+            .collect())
+    }
+
+    fn remove_container(&mut self, container: &RuntimeContainer) -> Result<(), Error> {
+        self.cli.remove(&container.container_id)
+    }
+
+    fn config_dirs(&mut self) -> Result<Vec<(Uuid, PathBuf)>, Error> {
+        compose::config_dirs()
+    }
+
+    fn remove_config_dir(&mut self, uuid: Uuid) -> Result<(), Error> {
+        compose::remove_config_dir(uuid)
+    }
+}
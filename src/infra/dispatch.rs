@@ -0,0 +1,163 @@
+use std::path::PathBuf;
+
+use log::{debug, warn};
+use uuid::Uuid;
+
+use crate::domain::repo::{
+    ContainerId, ContainerInfo, ContainerStatus, EnvRecord, EnvSpec, Error, PortMapping, Runtime,
+    RuntimeContainer, RuntimeKind, SharedResources,
+};
+use crate::util::command_exists;
+
+use super::compose;
+use super::compose_cli::ComposeCliForContainerRuntime;
+use super::docker_api::DockerApiForContainerRuntime;
+
+// dockerがインストールされていればdocker、そうでなければpodmanを選ぶ
+pub fn detect_runtime() -> RuntimeKind {
+    if command_exists("docker") {
+        RuntimeKind::Docker
+    } else if command_exists("podman") {
+        debug!("docker was not found. podman is used instead.");
+        RuntimeKind::Podman
+    } else {
+        RuntimeKind::Docker
+    }
+}
+
+// 新しい環境は既定のランタイムで作成し、既存の環境はそれを作成したランタイムで操作する
+pub struct RuntimeDispatcher {
+    default: RuntimeKind,
+    docker: ComposeCliForContainerRuntime,
+    podman: ComposeCliForContainerRuntime,
+    docker_api: DockerApiForContainerRuntime,
+}
+
+impl RuntimeDispatcher {
+    pub fn new(default: RuntimeKind) -> Self {
+        // 以前のバージョンが/tmpに作成した設定ディレクトリを引き継ぐ (移し終えていれば何もしない)
+        if let Err(err) = compose::migrate_legacy_config_dirs() {
+            warn!("Failed to migrate config directories from /tmp: {err}");
+        }
+
+        Self {
+            default,
+            docker: ComposeCliForContainerRuntime::new(RuntimeKind::Docker),
+            podman: ComposeCliForContainerRuntime::new(RuntimeKind::Podman),
+            docker_api: DockerApiForContainerRuntime::new(),
+        }
+    }
+
+    fn get(&mut self, kind: RuntimeKind) -> &mut dyn Runtime {
+        match kind {
+            RuntimeKind::Docker => &mut self.docker,
+            RuntimeKind::Podman => &mut self.podman,
+            RuntimeKind::DockerApi => &mut self.docker_api,
+        }
+    }
+}
+
+impl Runtime for RuntimeDispatcher {
+    fn init(
+        &mut self,
+        shared_resources: &SharedResources,
+        env_spec: &EnvSpec,
+    ) -> Result<ContainerInfo, Error> {
+        let kind = self.default;
+        self.get(kind).init(shared_resources, env_spec)
+    }
+
+    fn rebuild(
+        &mut self,
+        shared_resources: &SharedResources,
+        record: &EnvRecord,
+    ) -> Result<ContainerInfo, Error> {
+        self.get(record.container_info.runtime)
+            .rebuild(shared_resources, record)
+    }
+
+    fn enter(
+        &mut self,
+        record: &EnvRecord,
+        service: Option<&str>,
+        shell: Option<&str>,
+    ) -> Result<(), Error> {
+        self.get(record.container_info.runtime)
+            .enter(record, service, shell)
+    }
+
+    fn exec(
+        &mut self,
+        record: &EnvRecord,
+        service: Option<&str>,
+        argv: &[String],
+        tty: bool,
+    ) -> Result<i32, Error> {
+        self.get(record.container_info.runtime)
+            .exec(record, service, argv, tty)
+    }
+
+    fn stop(&mut self, record: &EnvRecord) -> Result<(), Error> {
+        self.get(record.container_info.runtime).stop(record)
+    }
+
+    fn start(&mut self, record: &EnvRecord) -> Result<(), Error> {
+        self.get(record.container_info.runtime).start(record)
+    }
+
+    fn kill(&mut self, record: &EnvRecord) -> Result<(), Error> {
+        self.get(record.container_info.runtime).kill(record)
+    }
+
+    fn inspect(
+        &mut self,
+        record: &EnvRecord,
+        container_id: &ContainerId,
+    ) -> Result<ContainerStatus, Error> {
+        self.get(record.container_info.runtime)
+            .inspect(record, container_id)
+    }
+
+    fn refresh(&mut self, record: &EnvRecord) -> Result<Option<ContainerInfo>, Error> {
+        self.get(record.container_info.runtime).refresh(record)
+    }
+
+    fn published_ports(&mut self, record: &EnvRecord) -> Result<Vec<PortMapping>, Error> {
+        self.get(record.container_info.runtime)
+            .published_ports(record)
+    }
+
+    // 利用できるすべてのランタイムからコンテナを集める
+    fn containers(&mut self) -> Result<Vec<RuntimeContainer>, Error> {
+        let mut containers = Vec::new();
+        for kind in [
+            RuntimeKind::Docker,
+            RuntimeKind::Podman,
+            RuntimeKind::DockerApi,
+        ] {
+            // CLIがインストールされていないランタイムは飛ばす
+            if kind != RuntimeKind::DockerApi && !command_exists(kind.as_str()) {
+                continue;
+            }
+            match self.get(kind).containers() {
+                Ok(v) => containers.extend(v),
+                Err(err) => warn!("Failed to list containers of {kind}: {err}"),
+            }
+        }
+        Ok(containers)
+    }
+
+    fn remove_container(&mut self, container: &RuntimeContainer) -> Result<(), Error> {
+        self.get(container.runtime).remove_container(container)
+    }
+
+    fn config_dirs(&mut self) -> Result<Vec<(Uuid, PathBuf)>, Error> {
+        let kind = self.default;
+        self.get(kind).config_dirs()
+    }
+
+    fn remove_config_dir(&mut self, uuid: Uuid) -> Result<(), Error> {
+        let kind = self.default;
+        self.get(kind).remove_config_dir(uuid)
+    }
+}
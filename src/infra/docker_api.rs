@@ -0,0 +1,1344 @@
+use std::io::{self, BufRead, BufReader, IsTerminal, Read, Write};
+use std::os::fd::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+use indexmap::IndexMap;
+use log::{info, warn};
+use serde_json::{Map, Value, json};
+use serde_yaml::Value as YamlValue;
+use uuid::Uuid;
+
+use crate::domain::repo::{
+    ContainerId, ContainerInfo, ContainerStatus, EnvRecord, EnvSpec, EnvState, Error, PortMapping,
+    Runtime, RuntimeContainer, RuntimeKind, SharedResources,
+};
+use crate::util::terminal::{self, InputForwarder, RawMode};
+
+use super::compose::{self, Compose, ImageBuild, Service};
+use super::engine::{EngineClient, encode_query};
+
+// コンテナに付与するラベル
+const UUID_LABEL: &str = "roxy.uuid";
+const SERVICE_LABEL: &str = "roxy.service";
+
+// compose.ymlのサービス設定のうち、Engine APIに変換できるもの
+const SUPPORTED_SERVICE_KEYS: &[&str] = &[
+    "build",
+    "image",
+    "user",
+    "network_mode",
+    "tty",
+    "stdin_open",
+    "privileged",
+    "ulimits",
+    "cap_add",
+    "cap_drop",
+    "security_opt",
+    "ports",
+    "environment",
+    "working_dir",
+    "command",
+    "entrypoint",
+    "hostname",
+    "cpus",
+    "mem_limit",
+    "pids_limit",
+];
+
+pub struct DockerApiForContainerRuntime {}
+
+// 作り直すあいだ退避しているコンテナ
+struct SetAside {
+    container_id: ContainerId,
+    // 名前を変えた場合は元の名前 (元の名前が分からない場合は変えたままにする)
+    name: Option<String>,
+    // 退避する前に起動していたかどうか
+    running: bool,
+}
+
+impl DockerApiForContainerRuntime {
+    pub fn new() -> Self {
+        Self {}
+    }
+
+    fn client(&self) -> Result<EngineClient, Error> {
+        EngineClient::from_env()
+    }
+
+    // サービスのイメージを用意し、そのイメージ名を返す
+    fn prepare_image(
+        &self,
+        client: &EngineClient,
+        config_path: &Path,
+        env_spec: &EnvSpec,
+        service_name: &str,
+        service: &Service,
+        images: &[ImageBuild],
+    ) -> Result<String, Error> {
+        match (service.other.get("build"), service.other.get("image")) {
+            (Some(build), image) => {
+                let (context, dockerfile, args) = match build {
+                    YamlValue::String(context) => {
+                        (context.clone(), "Dockerfile".to_string(), IndexMap::new())
+                    }
+                    YamlValue::Mapping(m) => {
+                        // Engine APIのビルドに渡すのはcontext、dockerfile、argsのみ
+                        for key in m.keys().filter_map(YamlValue::as_str) {
+                            if !compose::SHARED_BUILD_KEYS.contains(&key) {
+                                warn!(
+                                    "build.{key} in service {service_name} is ignored by the docker-api runtime."
+                                );
+                            }
+                        }
+                        (
+                            m.get("context")
+                                .and_then(YamlValue::as_str)
+                                .unwrap_or(".")
+                                .to_string(),
+                            m.get("dockerfile")
+                                .and_then(YamlValue::as_str)
+                                .unwrap_or("Dockerfile")
+                                .to_string(),
+                            compose::build_args(service_name, m.get("args"))?,
+                        )
+                    }
+                    _ => {
+                        return Err(Error::InvalidComposeConfig {
+                            reason: format!("unsupported build section in service {service_name}"),
+                        });
+                    }
+                };
+                let tag = match image.and_then(YamlValue::as_str) {
+                    Some(image) => image.to_string(),
+                    None => format!("roxy-{}-{}", env_spec.uuid, service_name).to_lowercase(),
+                };
+                self.build(client, &config_path.join(context), &dockerfile, &args, &tag)?;
+                Ok(tag)
+            }
+            // 環境のあいだで共有するイメージは既にビルドしてあるので取得しない
+            (None, Some(YamlValue::String(image)))
+                if images.iter().any(|shared| shared.tag == *image) =>
+            {
+                Ok(image.clone())
+            }
+            (None, Some(YamlValue::String(image))) => {
+                self.pull(client, image)?;
+                Ok(image.clone())
+            }
+            _ => Err(Error::InvalidComposeConfig {
+                reason: format!("service {service_name} has neither build nor image"),
+            }),
+        }
+    }
+
+    // 環境のあいだで共有するイメージがなければビルドする
+    fn ensure_image(&self, client: &EngineClient, image: &ImageBuild) -> Result<(), Error> {
+        let response = client.request_json(
+            "GET",
+            &format!("/images/{}/json", encode_query(&image.tag)),
+            None,
+        )?;
+        if response.status != 404 {
+            response.error_for_status()?;
+            info!("Using the existing image {}", image.tag);
+            return Ok(());
+        }
+
+        self.build(
+            client,
+            &image.context,
+            &image.dockerfile,
+            &image.args,
+            &image.tag,
+        )
+    }
+
+    // ビルドコンテキストをtarにしてイメージをビルドする
+    fn build(
+        &self,
+        client: &EngineClient,
+        context: &Path,
+        dockerfile: &str,
+        args: &IndexMap<String, String>,
+        tag: &str,
+    ) -> Result<(), Error> {
+        let mut builder = tar::Builder::new(Vec::new());
+        builder
+            .append_dir_all(".", context)
+            .map_err(|err| Error::Io {
+                path: Some(context.to_path_buf()),
+                source: err,
+            })?;
+        let archive = builder.into_inner().map_err(|err| Error::Io {
+            path: Some(context.to_path_buf()),
+            source: err,
+        })?;
+
+        let mut path = format!(
+            "/build?t={}&dockerfile={}&rm=1",
+            encode_query(tag),
+            encode_query(dockerfile)
+        );
+        if !args.is_empty() {
+            let args = serde_json::to_string(args).map_err(Error::Json)?;
+            path.push_str(&format!("&buildargs={}", encode_query(&args)));
+        }
+        let response = client
+            .request("POST", &path, "application/x-tar", &archive)?
+            .error_for_status()?;
+
+        Self::follow_progress(response.body)
+    }
+
+    fn pull(&self, client: &EngineClient, image: &str) -> Result<(), Error> {
+        let path = format!("/images/create?fromImage={}", encode_query(image));
+        let response = client.request("POST", &path, "", &[])?.error_for_status()?;
+
+        Self::follow_progress(response.body)
+    }
+
+    // build/pullの進捗を表示し、途中で報告されたエラーを返す
+    fn follow_progress(body: Box<dyn Read>) -> Result<(), Error> {
+        let reader = BufReader::new(body);
+        let mut stderr = io::stderr();
+
+        for line in reader.lines() {
+            let line = line.map_err(|err| Error::Io {
+                path: None,
+                source: err,
+            })?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let message: Value = serde_json::from_str(&line).map_err(Error::Json)?;
+
+            if let Some(err) = message.get("error").and_then(Value::as_str) {
+                return Err(Error::Api {
+                    status: 200,
+                    message: err.to_string(),
+                });
+            }
+            if let Some(stream) = message.get("stream").and_then(Value::as_str) {
+                let _ = stderr.write_all(stream.as_bytes());
+            } else if let Some(status) = message.get("status").and_then(Value::as_str) {
+                let _ = writeln!(stderr, "{status}");
+            }
+        }
+
+        Ok(())
+    }
+
+    // compose.ymlのサービス設定をコンテナ作成リクエストのボディに変換する
+    // networkが指定された場合は、network_modeのないサービスをそのネットワークにサービス名で接続する
+    fn create_body(
+        env_spec: &EnvSpec,
+        service_name: &str,
+        service: &Service,
+        image: &str,
+        network: Option<&str>,
+    ) -> Result<Value, Error> {
+        let invalid = |key: &str| Error::InvalidComposeConfig {
+            reason: format!("unsupported {key} in service {service_name}"),
+        };
+
+        for key in service.other.keys() {
+            if !SUPPORTED_SERVICE_KEYS.contains(&key.as_str()) {
+                warn!("{key} in service {service_name} is ignored by the docker-api runtime.");
+            }
+        }
+
+        let other = &service.other;
+        let as_json = |v: &YamlValue| serde_json::to_value(v).map_err(Error::Json);
+        let strings = |key: &str| -> Result<Option<Vec<String>>, Error> {
+            match other.get(key) {
+                None => Ok(None),
+                Some(YamlValue::Sequence(seq)) => seq
+                    .iter()
+                    .map(|v| match v {
+                        YamlValue::String(s) => Ok(s.clone()),
+                        YamlValue::Number(n) => Ok(n.to_string()),
+                        _ => Err(invalid(key)),
+                    })
+                    .collect::<Result<Vec<_>, _>>()
+                    .map(Some),
+                Some(YamlValue::String(s)) => {
+                    Ok(Some(s.split_whitespace().map(String::from).collect()))
+                }
+                Some(_) => Err(invalid(key)),
+            }
+        };
+
+        let mut config = Map::new();
+        let mut host_config = Map::new();
+
+        config.insert("Image".into(), json!(image));
+        config.insert(
+            "Labels".into(),
+            json!({ UUID_LABEL: env_spec.uuid.to_string(), SERVICE_LABEL: service_name }),
+        );
+
+        if let Some(v) = other.get("user") {
+            config.insert("User".into(), as_json(v)?);
+        }
+        if let Some(v) = other.get("tty") {
+            config.insert("Tty".into(), as_json(v)?);
+        }
+        if let Some(v) = other.get("stdin_open") {
+            config.insert("OpenStdin".into(), as_json(v)?);
+        }
+        if let Some(v) = other.get("working_dir") {
+            config.insert("WorkingDir".into(), as_json(v)?);
+        }
+        if let Some(v) = other.get("hostname") {
+            config.insert("Hostname".into(), as_json(v)?);
+        }
+        if let Some(v) = strings("command")? {
+            config.insert("Cmd".into(), json!(v));
+        }
+        if let Some(v) = strings("entrypoint")? {
+            config.insert("Entrypoint".into(), json!(v));
+        }
+
+        // environmentはリスト形式とマップ形式の両方を受け付ける
+        match other.get("environment") {
+            None => {}
+            Some(YamlValue::Mapping(m)) => {
+                let env = m
+                    .iter()
+                    .map(|(k, v)| {
+                        let k = k.as_str().ok_or_else(|| invalid("environment"))?;
+                        let v = match v {
+                            YamlValue::String(s) => s.clone(),
+                            YamlValue::Null => String::new(),
+                            other => serde_yaml::to_string(other)
+                                .map_err(Error::YamlDe)?
+                                .trim()
+                                .to_string(),
+                        };
+                        Ok(format!("{k}={v}"))
+                    })
+                    .collect::<Result<Vec<_>, Error>>()?;
+                config.insert("Env".into(), json!(env));
+            }
+            Some(_) => {
+                config.insert("Env".into(), json!(strings("environment")?));
+            }
+        }
+
+        if let Some(v) = other.get("network_mode") {
+            host_config.insert("NetworkMode".into(), as_json(v)?);
+        } else if let Some(network) = network {
+            host_config.insert("NetworkMode".into(), json!(network));
+            config.insert(
+                "NetworkingConfig".into(),
+                json!({ "EndpointsConfig": { network: { "Aliases": [service_name] } } }),
+            );
+        }
+        if let Some(v) = other.get("privileged") {
+            host_config.insert("Privileged".into(), as_json(v)?);
+        }
+        if let Some(v) = strings("cap_add")? {
+            host_config.insert("CapAdd".into(), json!(v));
+        }
+        if let Some(v) = strings("cap_drop")? {
+            host_config.insert("CapDrop".into(), json!(v));
+        }
+        if let Some(v) = strings("security_opt")? {
+            host_config.insert("SecurityOpt".into(), json!(v));
+        }
+
+        // リソース制限
+        if let Some(cpus) = other.get("cpus") {
+            let cpus = match cpus {
+                YamlValue::Number(n) => n.as_f64(),
+                YamlValue::String(s) => s.parse::<f64>().ok(),
+                _ => None,
+            }
+            .ok_or_else(|| invalid("cpus"))?;
+            host_config.insert("NanoCpus".into(), json!((cpus * 1e9) as i64));
+        }
+        if let Some(memory) = other.get("mem_limit") {
+            let memory = match memory {
+                YamlValue::Number(n) => n.as_i64(),
+                YamlValue::String(s) => parse_bytes(s),
+                _ => None,
+            }
+            .ok_or_else(|| invalid("mem_limit"))?;
+            host_config.insert("Memory".into(), json!(memory));
+        }
+        if let Some(pids) = other.get("pids_limit") {
+            let pids = pids.as_i64().ok_or_else(|| invalid("pids_limit"))?;
+            host_config.insert("PidsLimit".into(), json!(pids));
+        }
+
+        // ulimitsは数値指定とsoft/hard指定の両方を受け付ける
+        if let Some(YamlValue::Mapping(m)) = other.get("ulimits") {
+            let mut ulimits = Vec::new();
+            for (name, limit) in m {
+                let name = name.as_str().ok_or_else(|| invalid("ulimits"))?;
+                let (soft, hard) = match limit {
+                    YamlValue::Number(n) => {
+                        let n = n.as_i64().ok_or_else(|| invalid("ulimits"))?;
+                        (n, n)
+                    }
+                    YamlValue::Mapping(l) => (
+                        l.get("soft")
+                            .and_then(YamlValue::as_i64)
+                            .ok_or_else(|| invalid("ulimits"))?,
+                        l.get("hard")
+                            .and_then(YamlValue::as_i64)
+                            .ok_or_else(|| invalid("ulimits"))?,
+                    ),
+                    _ => return Err(invalid("ulimits")),
+                };
+                ulimits.push(json!({ "Name": name, "Soft": soft, "Hard": hard }));
+            }
+            host_config.insert("Ulimits".into(), json!(ulimits));
+        }
+
+        // portsは "[ip:]host:container[/proto]" 形式の文字列のみ受け付ける
+        if let Some(ports) = strings("ports")? {
+            let mut exposed = Map::new();
+            let mut bindings = Map::new();
+            for port in ports {
+                let (spec, proto) = port.split_once('/').unwrap_or((&port, "tcp"));
+                let parts = spec.rsplitn(3, ':').collect::<Vec<_>>();
+                let (container, host, ip) = match parts.as_slice() {
+                    [container] => (*container, "", ""),
+                    [container, host] => (*container, *host, ""),
+                    [container, host, ip] => (*container, *host, *ip),
+                    _ => return Err(invalid("ports")),
+                };
+                let key = format!("{container}/{proto}");
+                exposed.insert(key.clone(), json!({}));
+                bindings
+                    .entry(key)
+                    .or_insert_with(|| json!([]))
+                    .as_array_mut()
+                    .unwrap()
+                    .push(json!({ "HostIp": ip, "HostPort": host }));
+            }
+            config.insert("ExposedPorts".into(), Value::Object(exposed));
+            host_config.insert("PortBindings".into(), Value::Object(bindings));
+        }
+
+        // volumesは "host:container[:mode]" 形式の文字列のみ受け付ける
+        if let Some(volumes) = &service.volumes {
+            let binds = volumes
+                .iter()
+                .map(|v| {
+                    v.as_str()
+                        .map(String::from)
+                        .ok_or_else(|| invalid("volumes"))
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            host_config.insert("Binds".into(), json!(binds));
+        }
+
+        config.insert("HostConfig".into(), Value::Object(host_config));
+
+        Ok(Value::Object(config))
+    }
+
+    // コンテナ内でコマンドを実行し、その終了コードを返す
+    fn exec_in(
+        &self,
+        container_id: &ContainerId,
+        argv: &[String],
+        tty: bool,
+    ) -> Result<i32, Error> {
+        let client = self.client()?;
+
+        let exec = client
+            .request_json(
+                "POST",
+                &format!("/containers/{container_id}/exec"),
+                Some(&json!({
+                    "AttachStdin": true,
+                    "AttachStdout": true,
+                    "AttachStderr": true,
+                    "Tty": tty,
+                    "Cmd": argv,
+                })),
+            )?
+            .json()?;
+        let exec_id = exec
+            .get("Id")
+            .and_then(Value::as_str)
+            .ok_or(Error::NotFound {
+                what: "exec id from docker engine API",
+            })?
+            .to_string();
+
+        let (mut reader, writer) = client.upgrade(
+            &format!("/exec/{exec_id}/start"),
+            &json!({ "Detach": false, "Tty": tty }),
+        )?;
+
+        // 端末の大きさをコンテナ側に伝え、rawモードに切り替える
+        let stdin = io::stdin();
+        let raw_mode = if tty && stdin.is_terminal() {
+            if let Some((h, w)) = terminal::size(stdin.as_raw_fd()) {
+                let _ = client.request_json(
+                    "POST",
+                    &format!("/exec/{exec_id}/resize?h={h}&w={w}"),
+                    None,
+                );
+            }
+            Some(RawMode::enable(stdin.as_raw_fd()).map_err(|err| Error::Io {
+                path: None,
+                source: err,
+            })?)
+        } else {
+            None
+        };
+
+        // 標準入力をコンテナに転送する (execが終了したら転送をやめ、次の入力を奪わないようにする)
+        let forwarder =
+            InputForwarder::spawn(stdin.as_raw_fd(), writer).map_err(|err| Error::Io {
+                path: None,
+                source: err,
+            })?;
+
+        // コンテナの出力を転送する
+        let result = if tty {
+            io::copy(&mut reader, &mut io::stdout()).map(|_| ())
+        } else {
+            Self::demux(&mut reader, &mut io::stdout(), &mut io::stderr())
+        };
+        drop(forwarder);
+        drop(raw_mode);
+        result.map_err(|err| Error::Io {
+            path: None,
+            source: err,
+        })?;
+
+        // 出力が閉じられた直後はまだ終了していないことがあるので、終了するまで待つ
+        let exit_code = Self::wait_exec(&client, &exec_id)?;
+
+        Ok(exit_code as i32)
+    }
+
+    // execが終了するまで待ち、その終了コードを返す
+    fn wait_exec(client: &EngineClient, exec_id: &str) -> Result<i64, Error> {
+        loop {
+            let inspect = client
+                .request_json("GET", &format!("/exec/{exec_id}/json"), None)?
+                .json()?;
+            let running = inspect
+                .get("Running")
+                .and_then(Value::as_bool)
+                .unwrap_or(false);
+            if !running {
+                // 終了コードが分からない場合に成功として扱わないようにエラーにする
+                return inspect
+                    .get("ExitCode")
+                    .and_then(Value::as_i64)
+                    .ok_or(Error::NotFound {
+                        what: "exit code of the exec",
+                    });
+            }
+            thread::sleep(Duration::from_millis(50));
+        }
+    }
+
+    // 入出力を接続せずにコマンドを実行し、成功したかどうかを返す
+    fn probe_in(&self, container_id: &ContainerId, argv: &[String]) -> Result<bool, Error> {
+        let client = self.client()?;
+
+        let exec = client
+            .request_json(
+                "POST",
+                &format!("/containers/{container_id}/exec"),
+                Some(&json!({ "Tty": false, "Cmd": argv })),
+            )?
+            .json()?;
+        let exec_id = exec
+            .get("Id")
+            .and_then(Value::as_str)
+            .ok_or(Error::NotFound {
+                what: "exec id from docker engine API",
+            })?
+            .to_string();
+
+        client
+            .request_json(
+                "POST",
+                &format!("/exec/{exec_id}/start"),
+                Some(&json!({ "Detach": true, "Tty": false })),
+            )?
+            .error_for_status()?;
+
+        // 終了するまで待つ
+        Ok(Self::wait_exec(&client, &exec_id)? == 0)
+    }
+
+    // TTYなしのexecで多重化された標準出力と標準エラー出力を分離する
+    fn demux(
+        reader: &mut impl Read,
+        stdout: &mut impl Write,
+        stderr: &mut impl Write,
+    ) -> io::Result<()> {
+        let mut header = [0u8; 8];
+        loop {
+            if let Err(err) = reader.read_exact(&mut header) {
+                if err.kind() == io::ErrorKind::UnexpectedEof {
+                    return Ok(());
+                }
+                return Err(err);
+            }
+
+            let len = u32::from_be_bytes([header[4], header[5], header[6], header[7]]) as u64;
+            let mut frame = reader.by_ref().take(len);
+            match header[0] {
+                2 => io::copy(&mut frame, stderr)?,
+                _ => io::copy(&mut frame, stdout)?,
+            };
+        }
+    }
+
+    // 環境のサービス同士が名前で通信するためのネットワークを作成する (既に存在する場合はそれを使う)
+    fn ensure_network(&self, client: &EngineClient, env_spec: &EnvSpec) -> Result<String, Error> {
+        let name = network_name(env_spec.uuid);
+        let response = client.request_json(
+            "POST",
+            "/networks/create",
+            Some(&json!({
+                "Name": name,
+                "Labels": { UUID_LABEL: env_spec.uuid.to_string() },
+            })),
+        )?;
+        if response.status != 409 {
+            response.error_for_status()?;
+        }
+        Ok(name)
+    }
+
+    // サービスのコンテナをimageから作成し、そのidを返す
+    fn create_service(
+        &self,
+        client: &EngineClient,
+        env_spec: &EnvSpec,
+        service_name: &str,
+        service: &Service,
+        image: &str,
+        network: Option<&str>,
+    ) -> Result<ContainerId, Error> {
+        let body = Self::create_body(env_spec, service_name, service, image, network)?;
+        let name = format!("roxy-{}-{}", env_spec.uuid, service_name).to_lowercase();
+        let created = client
+            .request_json(
+                "POST",
+                &format!("/containers/create?name={}", encode_query(&name)),
+                Some(&body),
+            )?
+            .json()?;
+        created
+            .get("Id")
+            .and_then(Value::as_str)
+            .map(ContainerId::from_str)
+            .ok_or(Error::NotFound {
+                what: "container id from docker engine API",
+            })
+    }
+
+    // コンテナを起動し、そのイメージのidを返す
+    fn start_service(
+        &self,
+        client: &EngineClient,
+        container_id: &ContainerId,
+    ) -> Result<Option<String>, Error> {
+        client
+            .request_json("POST", &format!("/containers/{container_id}/start"), None)?
+            .error_for_status()?;
+
+        // 起動したことを確認する
+        let inspect = client
+            .request_json("GET", &format!("/containers/{container_id}/json"), None)?
+            .json()?;
+        let running = inspect
+            .pointer("/State/Running")
+            .and_then(Value::as_bool)
+            .unwrap_or(false);
+        if !running {
+            let status = inspect
+                .pointer("/State/Status")
+                .and_then(Value::as_str)
+                .unwrap_or("unknown");
+            return Err(Error::Api {
+                status: 200,
+                message: format!("container {container_id} is not running (status: {status})"),
+            });
+        }
+
+        Ok(inspect
+            .get("Image")
+            .and_then(Value::as_str)
+            .map(String::from))
+    }
+
+    // テンプレートから設定ディレクトリを作成し、すべてのサービスのコンテナを起動する
+    // replacedを渡した場合は、新しいコンテナがすべて起動してから古いコンテナを削除する
+    fn create(
+        &self,
+        shared_resources: &SharedResources,
+        env_spec: &EnvSpec,
+        replaced: Option<&EnvRecord>,
+    ) -> Result<ContainerInfo, Error> {
+        let client = self.client()?;
+
+        // テンプレートから作業用の設定ディレクトリを作成し、イメージを用意する
+        // ここで失敗した場合は、作り直す前の設定ディレクトリとコンテナをそのまま残す
+        let (config_path, images) =
+            compose::prepare_config_dir(shared_resources, env_spec, RuntimeKind::DockerApi)?;
+        let prepared = self.prepare_images(&client, &config_path, env_spec, &images);
+        let (compose, image_names) = match prepared {
+            Ok(o) => o,
+            Err(err) => {
+                let _ = compose::discard_config_dir(env_spec.uuid);
+                return Err(err);
+            }
+        };
+        compose::commit_config_dir(env_spec.uuid)?;
+        let primary = compose.primary_service()?;
+
+        // network_modeのないサービスはcomposeと同じく環境ごとのネットワークにつなぐ
+        let network = if compose
+            .services
+            .values()
+            .any(|service| !service.other.contains_key("network_mode"))
+        {
+            Some(self.ensure_network(&client, env_spec)?)
+        } else {
+            None
+        };
+
+        // 古いコンテナは名前とホストのポートを新しいコンテナに譲るために、停止して別の名前にしておく
+        let set_aside = match replaced {
+            Some(record) => self.set_aside(&client, record)?,
+            None => Vec::new(),
+        };
+
+        let created = self.create_services(
+            &client,
+            env_spec,
+            &compose,
+            &image_names,
+            network.as_deref(),
+        );
+        let (services, image_id) = match created {
+            Ok(o) => o,
+            Err(err) => {
+                // 古いコンテナを元に戻す
+                if let Err(err) = self.restore(&client, &set_aside) {
+                    warn!("Failed to restore the previous containers: {err}");
+                }
+                return Err(err);
+            }
+        };
+
+        // 新しいコンテナがすべて起動したので古いコンテナを削除する
+        if let Some(record) = replaced {
+            self.remove_containers(&client, record)?;
+        }
+
+        Ok(ContainerInfo {
+            container_id: services[&primary].clone(),
+            services,
+            image_id,
+            runtime: RuntimeKind::DockerApi,
+            state: EnvState::Running,
+            ports: compose::published_ports(&compose)?,
+        })
+    }
+
+    // すべてのサービスのイメージをビルドもしくは取得し、compose.ymlとサービスごとのイメージ名を返す
+    fn prepare_images(
+        &self,
+        client: &EngineClient,
+        config_path: &Path,
+        env_spec: &EnvSpec,
+        images: &[ImageBuild],
+    ) -> Result<(Compose, Vec<String>), Error> {
+        for image in images {
+            self.ensure_image(client, image)?;
+        }
+        let compose = compose::load_compose(config_path)?;
+        let image_names = compose
+            .services
+            .iter()
+            .map(|(service_name, service)| {
+                self.prepare_image(client, config_path, env_spec, service_name, service, images)
+            })
+            .collect::<Result<_, _>>()?;
+        Ok((compose, image_names))
+    }
+
+    // compose.ymlに書かれた順にサービスのコンテナを作成して起動し、
+    // サービス名とコンテナidの組と主サービスのイメージのidを返す
+    // 途中で失敗した場合は、それまでに作成したコンテナを削除する
+    fn create_services(
+        &self,
+        client: &EngineClient,
+        env_spec: &EnvSpec,
+        compose: &Compose,
+        image_names: &[String],
+        network: Option<&str>,
+    ) -> Result<(IndexMap<String, ContainerId>, Option<String>), Error> {
+        let primary = compose.primary_service()?;
+        let mut services = IndexMap::new();
+        let mut image_id = None;
+
+        let mut result = Ok(());
+        for ((service_name, service), image_name) in compose.services.iter().zip(image_names) {
+            let container_id = match self.create_service(
+                client,
+                env_spec,
+                service_name,
+                service,
+                image_name,
+                network,
+            ) {
+                Ok(o) => o,
+                Err(err) => {
+                    result = Err(err);
+                    break;
+                }
+            };
+            services.insert(service_name.clone(), container_id.clone());
+
+            match self.start_service(client, &container_id) {
+                Ok(image) if *service_name == primary => image_id = image,
+                Ok(_) => {}
+                Err(err) => {
+                    result = Err(err);
+                    break;
+                }
+            }
+        }
+
+        if let Err(err) = result {
+            for container_id in services.values() {
+                if let Err(err) = self.remove_container_by_id(client, container_id) {
+                    warn!("Failed to remove the container {container_id}: {err}");
+                }
+            }
+            return Err(err);
+        }
+
+        Ok((services, image_id))
+    }
+
+    // 作り直す前のコンテナを停止して別の名前にする
+    // 途中で失敗した場合は、それまでに退避したコンテナを元に戻す
+    fn set_aside(&self, client: &EngineClient, record: &EnvRecord) -> Result<Vec<SetAside>, Error> {
+        let mut set_aside = Vec::new();
+        for container_id in record.container_info.container_ids() {
+            let result = self.set_aside_container(client, container_id, &mut set_aside);
+            if let Err(err) = result {
+                if let Err(err) = self.restore(client, &set_aside) {
+                    warn!("Failed to restore the previous containers: {err}");
+                }
+                return Err(err);
+            }
+        }
+        Ok(set_aside)
+    }
+
+    fn set_aside_container(
+        &self,
+        client: &EngineClient,
+        container_id: &ContainerId,
+        set_aside: &mut Vec<SetAside>,
+    ) -> Result<(), Error> {
+        let response =
+            client.request_json("GET", &format!("/containers/{container_id}/json"), None)?;
+        // roxyの外で削除されたコンテナは元に戻せないので飛ばす
+        if response.status == 404 {
+            return Ok(());
+        }
+        let inspect = response.json()?;
+        let name = inspect
+            .get("Name")
+            .and_then(Value::as_str)
+            .unwrap_or_default()
+            .trim_start_matches('/')
+            .to_string();
+        let name = Some(name).filter(|name| !name.is_empty());
+        let running = inspect
+            .pointer("/State/Running")
+            .and_then(Value::as_bool)
+            .unwrap_or(false);
+
+        // 停止したものは元に戻すときに起動し直す
+        if running {
+            client
+                .request_json("POST", &format!("/containers/{container_id}/stop"), None)?
+                .error_for_status()?;
+        }
+        set_aside.push(SetAside {
+            container_id: container_id.clone(),
+            name: None,
+            running,
+        });
+
+        let replaced_name = format!("roxy-replaced-{container_id}");
+        client
+            .request_json(
+                "POST",
+                &format!(
+                    "/containers/{container_id}/rename?name={}",
+                    encode_query(&replaced_name)
+                ),
+                None,
+            )?
+            .error_for_status()?;
+        if let Some(entry) = set_aside.last_mut() {
+            entry.name = name;
+        }
+        Ok(())
+    }
+
+    // set_asideで退避したコンテナを元の名前に戻し、起動していたものは起動し直す
+    fn restore(&self, client: &EngineClient, set_aside: &[SetAside]) -> Result<(), Error> {
+        for entry in set_aside {
+            let container_id = &entry.container_id;
+            if let Some(name) = &entry.name {
+                client
+                    .request_json(
+                        "POST",
+                        &format!(
+                            "/containers/{container_id}/rename?name={}",
+                            encode_query(name)
+                        ),
+                        None,
+                    )?
+                    .error_for_status()?;
+            }
+            if entry.running {
+                client
+                    .request_json("POST", &format!("/containers/{container_id}/start"), None)?
+                    .error_for_status()?;
+            }
+        }
+        Ok(())
+    }
+
+    // コンテナを削除する (既に存在しないものは無視する)
+    fn remove_container_by_id(
+        &self,
+        client: &EngineClient,
+        container_id: &ContainerId,
+    ) -> Result<(), Error> {
+        let response = client.request_json(
+            "DELETE",
+            &format!("/containers/{container_id}?force=true"),
+            None,
+        )?;
+        if response.status != 404 {
+            response.error_for_status()?;
+        }
+        Ok(())
+    }
+
+    // 環境のコンテナをすべて削除する (既に存在しないものは無視する)
+    fn remove_containers(&self, client: &EngineClient, record: &EnvRecord) -> Result<(), Error> {
+        for container_id in record.container_info.container_ids() {
+            self.remove_container_by_id(client, container_id)?;
+        }
+        Ok(())
+    }
+}
+
+impl Runtime for DockerApiForContainerRuntime {
+    fn init(
+        &mut self,
+        shared_resources: &SharedResources,
+        env_spec: &EnvSpec,
+    ) -> Result<ContainerInfo, Error> {
+        self.create(shared_resources, env_spec, None)
+    }
+
+    fn rebuild(
+        &mut self,
+        shared_resources: &SharedResources,
+        record: &EnvRecord,
+    ) -> Result<ContainerInfo, Error> {
+        // 同じEnvSpecでコンテナを作り直す (古いコンテナは作り直せてから削除する)
+        self.create(shared_resources, &record.spec, Some(record))
+    }
+
+    fn enter(
+        &mut self,
+        record: &EnvRecord,
+        service: Option<&str>,
+        shell: Option<&str>,
+    ) -> Result<(), Error> {
+        let container_id = record.container_info.container(service)?;
+        let primary = container_id == &record.container_info.container_id;
+        let shell = compose::resolve_shell(record.spec.uuid, shell, primary, |argv| {
+            self.probe_in(container_id, argv)
+        })?;
+
+        self.exec_in(container_id, &[shell], true)?;
+        Ok(())
+    }
+
+    fn exec(
+        &mut self,
+        record: &EnvRecord,
+        service: Option<&str>,
+        argv: &[String],
+        tty: bool,
+    ) -> Result<i32, Error> {
+        let container_id = record.container_info.container(service)?;
+        self.exec_in(container_id, argv, tty)
+    }
+
+    fn stop(&mut self, record: &EnvRecord) -> Result<(), Error> {
+        let client = self.client()?;
+        for container_id in record.container_info.container_ids() {
+            client
+                .request_json("POST", &format!("/containers/{container_id}/stop"), None)?
+                .error_for_status()?;
+        }
+        Ok(())
+    }
+
+    fn start(&mut self, record: &EnvRecord) -> Result<(), Error> {
+        let client = self.client()?;
+        for container_id in record.container_info.container_ids() {
+            client
+                .request_json("POST", &format!("/containers/{container_id}/start"), None)?
+                .error_for_status()?;
+        }
+        Ok(())
+    }
+
+    fn kill(&mut self, record: &EnvRecord) -> Result<(), Error> {
+        let client = self.client()?;
+
+        // コンテナを停止して削除する (roxyの外で削除されたコンテナは削除済みとみなす)
+        self.remove_containers(&client, record)?;
+
+        // 環境のネットワークを削除する (すべてのサービスがnetwork_modeを持つ環境では作成されていない)
+        let response = client.request_json(
+            "DELETE",
+            &format!("/networks/{}", network_name(record.spec.uuid)),
+            None,
+        )?;
+        if response.status != 404 {
+            response.error_for_status()?;
+        }
+
+        // 設定ディレクトリを削除する
+        compose::remove_config_dir(record.spec.uuid)
+    }
+
+    fn inspect(
+        &mut self,
+        _record: &EnvRecord,
+        container_id: &ContainerId,
+    ) -> Result<ContainerStatus, Error> {
+        let response = self.client()?.request_json(
+            "GET",
+            &format!("/containers/{container_id}/json"),
+            None,
+        )?;
+        if response.status == 404 {
+            return Ok(ContainerStatus::Missing);
+        }
+
+        let inspect = response.json()?;
+        let status = inspect
+            .pointer("/State/Status")
+            .and_then(Value::as_str)
+            .unwrap_or_default();
+        Ok(compose::status_from_str(status))
+    }
+
+    fn refresh(&mut self, record: &EnvRecord) -> Result<Option<ContainerInfo>, Error> {
+        // 作成時に付与したラベルから環境のコンテナを探す
+        let filters = json!({ "label": [format!("{UUID_LABEL}={}", record.spec.uuid)] });
+        let containers = self
+            .client()?
+            .request_json(
+                "GET",
+                &format!(
+                    "/containers/json?all=1&filters={}",
+                    encode_query(&filters.to_string())
+                ),
+                None,
+            )?
+            .json()?;
+
+        let mut services = IndexMap::new();
+        for container in containers.as_array().into_iter().flatten() {
+            let id = container.get("Id").and_then(Value::as_str);
+            let service = container
+                .pointer(&format!("/Labels/{SERVICE_LABEL}"))
+                .and_then(Value::as_str);
+            if let (Some(id), Some(service)) = (id, service) {
+                services.insert(service.to_string(), ContainerId::from_str(id));
+            }
+        }
+
+        // 主サービスは記録されたサービス名、設定ディレクトリのcompose.ymlの順に特定する
+        let info = &record.container_info;
+        let primary = info
+            .services
+            .iter()
+            .find(|(_, id)| **id == info.container_id)
+            .map(|(name, _)| name.clone())
+            .or_else(|| {
+                compose::load_compose(&compose::config_dir_path(record.spec.uuid))
+                    .and_then(|compose| compose.primary_service())
+                    .ok()
+            });
+        let container_id = match primary {
+            Some(primary) => services.get(&primary).cloned(),
+            None if services.len() == 1 => services.values().next().cloned(),
+            None => None,
+        };
+
+        Ok(container_id.map(|container_id| ContainerInfo {
+            container_id,
+            services,
+            ..info.clone()
+        }))
+    }
+
+    fn published_ports(&mut self, record: &EnvRecord) -> Result<Vec<PortMapping>, Error> {
+        compose::load_compose(&compose::config_dir_path(record.spec.uuid))
+            .and_then(|compose| compose::published_ports(&compose))
+    }
+
+    fn containers(&mut self) -> Result<Vec<RuntimeContainer>, Error> {
+        let filters = json!({ "label": [UUID_LABEL] });
+        let containers = self
+            .client()?
+            .request_json(
+                "GET",
+                &format!(
+                    "/containers/json?all=1&filters={}",
+                    encode_query(&filters.to_string())
+                ),
+                None,
+            )?
+            .json()?;
+
+        Ok(containers
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|container| {
+                let id = container.get("Id").and_then(Value::as_str)?;
+                let uuid = container
+                    .pointer(&format!("/Labels/{UUID_LABEL}"))
+                    .and_then(Value::as_str)?;
+                Some(RuntimeContainer {
+                    uuid: Uuid::parse_str(uuid).ok()?,
+                    container_id: ContainerId::from_str(id),
+                    runtime: RuntimeKind::DockerApi,
+                })
+            })
+            .collect())
+    }
+
+    fn remove_container(&mut self, container: &RuntimeContainer) -> Result<(), Error> {
+        let response = self.client()?.request_json(
+            "DELETE",
+            &format!("/containers/{}?force=true", container.container_id),
+            None,
+        )?;
+        if response.status != 404 {
+            response.error_for_status()?;
+        }
+        Ok(())
+    }
+
+    fn config_dirs(&mut self) -> Result<Vec<(Uuid, PathBuf)>, Error> {
+        compose::config_dirs()
+    }
+
+    fn remove_config_dir(&mut self, uuid: Uuid) -> Result<(), Error> {
+        compose::remove_config_dir(uuid)
+    }
+}
+
+// 環境のサービス間で共有するネットワークの名前
+fn network_name(uuid: Uuid) -> String {
+    format!("roxy-{uuid}")
+}
+
+// "512m"や"2g"のような容量の表記をバイト数に変換する
+fn parse_bytes(s: &str) -> Option<i64> {
+    let s = s.trim().to_ascii_lowercase();
+    let s = s.strip_suffix('b').unwrap_or(&s);
+    let (number, unit) = match s.char_indices().last()? {
+        (i, 'k') => (&s[..i], 1 << 10),
+        (i, 'm') => (&s[..i], 1 << 20),
+        (i, 'g') => (&s[..i], 1 << 30),
+        _ => (s, 1),
+    };
+    let number = number.trim().parse::<f64>().ok()?;
+    Some((number * unit as f64) as i64)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use std::path::PathBuf;
+
+    use indexmap::IndexMap;
+    use uuid::Uuid;
+
+    use crate::domain::config::ProjectConfig;
+    use crate::domain::repo::EnvTimestamps;
+    use crate::infra::engine::tests::fake_daemon;
+
+    use super::*;
+
+    // 多重化されたストリームのフレーム
+    fn frame(stream: u8, data: &[u8]) -> Vec<u8> {
+        let mut out = vec![stream, 0, 0, 0];
+        out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+        out.extend_from_slice(data);
+        out
+    }
+
+    fn json_response(status: &str, body: &str) -> Vec<u8> {
+        format!(
+            "HTTP/1.1 {status}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{body}",
+            body.len()
+        )
+        .into_bytes()
+    }
+
+    #[test]
+    fn demux_splits_stdout_and_stderr() {
+        let mut input = frame(1, b"out1 ");
+        input.extend(frame(2, b"err"));
+        input.extend(frame(1, b""));
+        input.extend(frame(1, b"out2"));
+
+        let (mut stdout, mut stderr) = (Vec::new(), Vec::new());
+        DockerApiForContainerRuntime::demux(&mut Cursor::new(input), &mut stdout, &mut stderr)
+            .unwrap();
+        assert_eq!(stdout, b"out1 out2");
+        assert_eq!(stderr, b"err");
+    }
+
+    #[test]
+    fn demux_stops_at_truncated_header() {
+        let mut input = frame(1, b"out");
+        input.extend([2, 0, 0]);
+
+        let (mut stdout, mut stderr) = (Vec::new(), Vec::new());
+        DockerApiForContainerRuntime::demux(&mut Cursor::new(input), &mut stdout, &mut stderr)
+            .unwrap();
+        assert_eq!(stdout, b"out");
+        assert!(stderr.is_empty());
+    }
+
+    #[test]
+    fn demux_reads_multiplexed_exec_output() {
+        let mut response =
+            b"HTTP/1.1 101 UPGRADED\r\nContent-Type: application/vnd.docker.multiplexed-stream\r\nConnection: Upgrade\r\nUpgrade: tcp\r\n\r\n".to_vec();
+        response.extend(frame(1, b"hello\n"));
+        response.extend(frame(2, b"warning\n"));
+        response.extend(frame(1, b"bye\n"));
+        let (client, daemon) = fake_daemon(vec![response]);
+
+        let (mut reader, _writer) = client
+            .upgrade("/exec/abc/start", &json!({"Detach": false, "Tty": false}))
+            .unwrap();
+        let (mut stdout, mut stderr) = (Vec::new(), Vec::new());
+        DockerApiForContainerRuntime::demux(&mut reader, &mut stdout, &mut stderr).unwrap();
+        assert_eq!(stdout, b"hello\nbye\n");
+        assert_eq!(stderr, b"warning\n");
+        daemon.join().unwrap();
+    }
+
+    #[test]
+    fn wait_exec_polls_until_finished() {
+        let (client, daemon) = fake_daemon(vec![
+            json_response("200 OK", r#"{"Running":true,"ExitCode":null}"#),
+            json_response("200 OK", r#"{"Running":false,"ExitCode":3}"#),
+        ]);
+
+        assert_eq!(
+            DockerApiForContainerRuntime::wait_exec(&client, "abc").unwrap(),
+            3
+        );
+        let requests = daemon.join().unwrap();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[1].line, "GET /v1.41/exec/abc/json HTTP/1.1");
+    }
+
+    #[test]
+    fn wait_exec_without_exit_code_is_error() {
+        let (client, daemon) = fake_daemon(vec![json_response(
+            "200 OK",
+            r#"{"Running":false,"ExitCode":null}"#,
+        )]);
+
+        let err = DockerApiForContainerRuntime::wait_exec(&client, "abc").unwrap_err();
+        assert!(matches!(err, Error::NotFound { .. }));
+        daemon.join().unwrap();
+    }
+
+    #[test]
+    fn remove_containers_treats_missing_as_removed() {
+        let mut services = IndexMap::new();
+        services.insert("dev".to_string(), ContainerId::from_str("aaa"));
+        services.insert("db".to_string(), ContainerId::from_str("bbb"));
+        let record = EnvRecord {
+            spec: EnvSpec {
+                uuid: Uuid::new_v4(),
+                project_path: PathBuf::from("/tmp/project"),
+                project_name: "project".to_string(),
+                config: ProjectConfig::default(),
+                template_hash: None,
+            },
+            container_info: ContainerInfo {
+                container_id: ContainerId::from_str("aaa"),
+                services,
+                image_id: None,
+                runtime: RuntimeKind::DockerApi,
+                state: EnvState::Running,
+                ports: Vec::new(),
+            },
+            timestamps: EnvTimestamps::default(),
+        };
+
+        let (client, daemon) = fake_daemon(vec![
+            json_response("204 No Content", ""),
+            json_response("404 Not Found", r#"{"message":"No such container: bbb"}"#),
+        ]);
+        DockerApiForContainerRuntime::new()
+            .remove_containers(&client, &record)
+            .unwrap();
+        let requests = daemon.join().unwrap();
+        assert_eq!(
+            requests[0].line,
+            "DELETE /v1.41/containers/aaa?force=true HTTP/1.1"
+        );
+        assert_eq!(
+            requests[1].line,
+            "DELETE /v1.41/containers/bbb?force=true HTTP/1.1"
+        );
+
+        let (client, daemon) = fake_daemon(vec![json_response(
+            "500 Internal Server Error",
+            r#"{"message":"boom"}"#,
+        )]);
+        let err = DockerApiForContainerRuntime::new()
+            .remove_containers(&client, &record)
+            .unwrap_err();
+        assert!(matches!(err, Error::Api { status: 500, .. }));
+        daemon.join().unwrap();
+    }
+}
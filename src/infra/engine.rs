@@ -0,0 +1,542 @@
+use std::env;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+
+use serde_json::Value;
+
+use crate::domain::repo::Error;
+
+const DEFAULT_SOCKET_PATH: &str = "/var/run/docker.sock";
+const API_VERSION: &str = "v1.41";
+
+// 小文字に正規化したヘッダ名と値の組
+type Headers = Vec<(String, String)>;
+
+// Docker Engine APIのレスポンス
+pub struct Response {
+    pub status: u16,
+    pub body: Box<dyn Read>,
+}
+
+impl Response {
+    // ボディをすべて読み出す
+    pub fn read_all(mut self) -> Result<Vec<u8>, Error> {
+        let mut buf = Vec::new();
+        self.body.read_to_end(&mut buf).map_err(|err| Error::Io {
+            path: None,
+            source: err,
+        })?;
+        Ok(buf)
+    }
+
+    // ステータスコードがエラーを示している場合はボディのメッセージを含むエラーに変換する
+    pub fn error_for_status(self) -> Result<Self, Error> {
+        if self.status < 400 {
+            return Ok(self);
+        }
+
+        let status = self.status;
+        let body = self.read_all()?;
+        let message = serde_json::from_slice::<Value>(&body)
+            .ok()
+            .and_then(|v| v.get("message").and_then(Value::as_str).map(String::from))
+            .unwrap_or_else(|| String::from_utf8_lossy(&body).trim().to_string());
+
+        Err(Error::Api { status, message })
+    }
+
+    // ボディをJSONとして読み出す
+    pub fn json(self) -> Result<Value, Error> {
+        let body = self.error_for_status()?.read_all()?;
+        if body.is_empty() {
+            return Ok(Value::Null);
+        }
+        serde_json::from_slice(&body).map_err(Error::Json)
+    }
+}
+
+// unixソケット越しにDocker Engine APIを呼び出すHTTP/1.1クライアント
+pub struct EngineClient {
+    socket_path: PathBuf,
+}
+
+impl EngineClient {
+    pub fn new(socket_path: PathBuf) -> Self {
+        Self { socket_path }
+    }
+
+    // DOCKER_HOSTが設定されていればそれを、そうでなければ既定のソケットを使う
+    pub fn from_env() -> Result<Self, Error> {
+        let socket_path = match env::var("DOCKER_HOST") {
+            Ok(host) if !host.is_empty() => match host.strip_prefix("unix://") {
+                Some(path) => PathBuf::from(path),
+                None => {
+                    return Err(Error::InvalidPath {
+                        path: PathBuf::from(host),
+                        msg: "only unix:// DOCKER_HOST is supported".into(),
+                    });
+                }
+            },
+            _ => PathBuf::from(DEFAULT_SOCKET_PATH),
+        };
+
+        Ok(Self::new(socket_path))
+    }
+
+    fn connect(&self) -> Result<UnixStream, Error> {
+        UnixStream::connect(&self.socket_path).map_err(|err| Error::Io {
+            path: Some(self.socket_path.clone()),
+            source: err,
+        })
+    }
+
+    fn io_error(&self, err: io::Error) -> Error {
+        Error::Io {
+            path: Some(self.socket_path.clone()),
+            source: err,
+        }
+    }
+
+    // リクエストを送信し、ステータス行とヘッダを読み終えた状態のストリームを返す
+    fn send(
+        &self,
+        method: &str,
+        path: &str,
+        content_type: &str,
+        body: &[u8],
+        upgrade: bool,
+    ) -> Result<(u16, Headers, BufReader<UnixStream>), Error> {
+        let mut stream = self.connect()?;
+
+        let mut head = format!(
+            "{method} /{API_VERSION}{path} HTTP/1.1\r\nHost: docker\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\n",
+            body.len()
+        );
+        if upgrade {
+            head.push_str("Connection: Upgrade\r\nUpgrade: tcp\r\n");
+        } else {
+            head.push_str("Connection: close\r\n");
+        }
+        head.push_str("\r\n");
+
+        stream
+            .write_all(head.as_bytes())
+            .and_then(|_| stream.write_all(body))
+            .and_then(|_| stream.flush())
+            .map_err(|err| self.io_error(err))?;
+
+        let mut reader = BufReader::new(stream);
+
+        // ステータス行を読む
+        let mut line = String::new();
+        reader
+            .read_line(&mut line)
+            .map_err(|err| self.io_error(err))?;
+        let status = line
+            .split_whitespace()
+            .nth(1)
+            .and_then(|s| s.parse::<u16>().ok())
+            .ok_or_else(|| Error::Api {
+                status: 0,
+                message: format!("malformed status line: {}", line.trim()),
+            })?;
+
+        // ヘッダを読む
+        let mut headers = Vec::new();
+        loop {
+            line.clear();
+            reader
+                .read_line(&mut line)
+                .map_err(|err| self.io_error(err))?;
+            let header = line.trim_end();
+            if header.is_empty() {
+                break;
+            }
+            if let Some((name, value)) = header.split_once(':') {
+                headers.push((name.trim().to_ascii_lowercase(), value.trim().to_string()));
+            }
+        }
+
+        Ok((status, headers, reader))
+    }
+
+    pub fn request(
+        &self,
+        method: &str,
+        path: &str,
+        content_type: &str,
+        body: &[u8],
+    ) -> Result<Response, Error> {
+        let (status, headers, reader) = self.send(method, path, content_type, body, false)?;
+
+        let header = |name: &str| {
+            headers
+                .iter()
+                .find(|(n, _)| n == name)
+                .map(|(_, v)| v.as_str())
+        };
+
+        // ヘッダーの値は大文字小文字を区別しない (chunkedは最後に適用された符号化として指定される)
+        let chunked = header("transfer-encoding").is_some_and(|v| {
+            v.rsplit(',')
+                .next()
+                .is_some_and(|coding| coding.trim().eq_ignore_ascii_case("chunked"))
+        });
+        let body: Box<dyn Read> = if chunked {
+            Box::new(ChunkedReader::new(reader))
+        } else if let Some(len) = header("content-length").and_then(|v| v.parse::<u64>().ok()) {
+            Box::new(reader.take(len))
+        } else {
+            Box::new(reader)
+        };
+
+        Ok(Response { status, body })
+    }
+
+    pub fn request_json(
+        &self,
+        method: &str,
+        path: &str,
+        body: Option<&Value>,
+    ) -> Result<Response, Error> {
+        let body = match body {
+            Some(v) => serde_json::to_vec(v).map_err(Error::Json)?,
+            None => Vec::new(),
+        };
+        self.request(method, path, "application/json", &body)
+    }
+
+    // 接続を乗っ取って双方向のストリームとして返す (execのアタッチに使う)
+    pub fn upgrade(
+        &self,
+        path: &str,
+        body: &Value,
+    ) -> Result<(BufReader<UnixStream>, UnixStream), Error> {
+        let body = serde_json::to_vec(body).map_err(Error::Json)?;
+        let (status, _headers, reader) =
+            self.send("POST", path, "application/json", &body, true)?;
+
+        if status >= 400 {
+            let response = Response {
+                status,
+                body: Box::new(reader),
+            };
+            return Err(response.error_for_status().err().unwrap_or(Error::Api {
+                status,
+                message: String::new(),
+            }));
+        }
+
+        let writer = reader
+            .get_ref()
+            .try_clone()
+            .map_err(|err| self.io_error(err))?;
+
+        Ok((reader, writer))
+    }
+}
+
+// Transfer-Encoding: chunkedのボディを読み出す
+struct ChunkedReader<R: BufRead> {
+    inner: R,
+    remaining: usize,
+    done: bool,
+}
+
+impl<R: BufRead> ChunkedReader<R> {
+    fn new(inner: R) -> Self {
+        Self {
+            inner,
+            remaining: 0,
+            done: false,
+        }
+    }
+}
+
+impl<R: BufRead> Read for ChunkedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.done {
+            return Ok(0);
+        }
+
+        if self.remaining == 0 {
+            // チャンクサイズの行を読む
+            let mut line = String::new();
+            self.inner.read_line(&mut line)?;
+            let size = line.trim().split(';').next().unwrap_or_default();
+            let size = usize::from_str_radix(size, 16)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+
+            if size == 0 {
+                self.done = true;
+                return Ok(0);
+            }
+            self.remaining = size;
+        }
+
+        let len = buf.len().min(self.remaining);
+        let n = self.inner.read(&mut buf[..len])?;
+        if n == 0 {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof));
+        }
+        self.remaining -= n;
+
+        if self.remaining == 0 {
+            // チャンク末尾のCRLFを読み飛ばす
+            let mut crlf = String::new();
+            self.inner.read_line(&mut crlf)?;
+        }
+
+        Ok(n)
+    }
+}
+
+// パーセントエンコードする (クエリ文字列の値に使う)
+pub fn encode_query(value: &str) -> String {
+    let mut out = String::new();
+    for b in value.bytes() {
+        if b.is_ascii_alphanumeric() || b"-_.~".contains(&b) {
+            out.push(b as char);
+        } else {
+            out.push_str(&format!("%{b:02X}"));
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::os::unix::net::UnixListener;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread::{self, JoinHandle};
+
+    use serde_json::json;
+
+    use super::*;
+
+    // 受け取ったリクエスト (リクエスト行とボディ)
+    pub(crate) struct Request {
+        pub line: String,
+        pub body: String,
+    }
+
+    // 接続ごとに決められたレスポンスを1つずつ返すDocker Engineの代わり
+    pub(crate) fn fake_daemon(responses: Vec<Vec<u8>>) -> (EngineClient, JoinHandle<Vec<Request>>) {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let socket_path = env::temp_dir().join(format!(
+            "roxy-engine-test-{}-{}.sock",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::SeqCst)
+        ));
+        let _ = std::fs::remove_file(&socket_path);
+        let listener = UnixListener::bind(&socket_path).unwrap();
+
+        let path = socket_path.clone();
+        let handle = thread::spawn(move || {
+            let mut requests = Vec::new();
+            for response in responses {
+                let (stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream);
+
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                let mut len = 0;
+                loop {
+                    let mut header = String::new();
+                    reader.read_line(&mut header).unwrap();
+                    let header = header.trim_end();
+                    if header.is_empty() {
+                        break;
+                    }
+                    if let Some(v) = header.strip_prefix("Content-Length: ") {
+                        len = v.parse().unwrap();
+                    }
+                }
+                let mut body = vec![0; len];
+                reader.read_exact(&mut body).unwrap();
+
+                reader.get_mut().write_all(&response).unwrap();
+                requests.push(Request {
+                    line: line.trim_end().to_string(),
+                    body: String::from_utf8(body).unwrap(),
+                });
+            }
+            let _ = std::fs::remove_file(path);
+            requests
+        });
+
+        (EngineClient::new(socket_path), handle)
+    }
+
+    fn body_of(response: Response) -> String {
+        String::from_utf8(response.read_all().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn request_reads_content_length_body() {
+        let (client, daemon) = fake_daemon(vec![
+            b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello trailing".to_vec(),
+        ]);
+
+        let response = client
+            .request_json(
+                "POST",
+                "/containers/create?name=a",
+                Some(&json!({"Image": "x"})),
+            )
+            .unwrap();
+        assert_eq!(response.status, 200);
+        assert_eq!(body_of(response), "hello");
+
+        let requests = daemon.join().unwrap();
+        assert_eq!(
+            requests[0].line,
+            "POST /v1.41/containers/create?name=a HTTP/1.1"
+        );
+        assert_eq!(requests[0].body, r#"{"Image":"x"}"#);
+    }
+
+    #[test]
+    fn request_reads_chunked_body() {
+        let (client, daemon) = fake_daemon(vec![
+            b"HTTP/1.1 200 OK\r\nTransfer-Encoding: Chunked\r\n\r\n5\r\nhello\r\n7;ext=1\r\n, world\r\n0\r\n\r\n".to_vec(),
+        ]);
+
+        let response = client.request_json("GET", "/info", None).unwrap();
+        assert_eq!(body_of(response), "hello, world");
+        daemon.join().unwrap();
+    }
+
+    #[test]
+    fn request_reads_body_until_eof_without_length() {
+        let (client, daemon) = fake_daemon(vec![b"HTTP/1.1 200 OK\r\n\r\n{\"a\":1}".to_vec()]);
+
+        let value = client.request_json("GET", "/info", None).unwrap().json();
+        assert_eq!(value.unwrap(), json!({"a": 1}));
+        daemon.join().unwrap();
+    }
+
+    #[test]
+    fn error_status_uses_message_field() {
+        let body = r#"{"message":"No such container: abc"}"#;
+        let (client, daemon) = fake_daemon(vec![
+            format!(
+                "HTTP/1.1 404 Not Found\r\nContent-Length: {}\r\n\r\n{body}",
+                body.len()
+            )
+            .into_bytes(),
+            b"HTTP/1.1 500 Internal Server Error\r\nContent-Length: 6\r\n\r\noops\r\n".to_vec(),
+        ]);
+
+        let err = client
+            .request_json("GET", "/containers/abc/json", None)
+            .unwrap()
+            .json()
+            .unwrap_err();
+        assert!(
+            matches!(err, Error::Api { status: 404, ref message } if message == "No such container: abc")
+        );
+
+        let err = client
+            .request_json("GET", "/info", None)
+            .unwrap()
+            .error_for_status()
+            .err()
+            .unwrap();
+        assert!(matches!(err, Error::Api { status: 500, ref message } if message == "oops"));
+        daemon.join().unwrap();
+    }
+
+    #[test]
+    fn json_of_empty_body_is_null() {
+        let (client, daemon) = fake_daemon(vec![
+            b"HTTP/1.1 204 No Content\r\nContent-Length: 0\r\n\r\n".to_vec(),
+        ]);
+
+        let value = client.request_json("POST", "/containers/a/start", None);
+        assert_eq!(value.unwrap().json().unwrap(), Value::Null);
+        daemon.join().unwrap();
+    }
+
+    #[test]
+    fn malformed_status_line_is_error() {
+        let (client, daemon) = fake_daemon(vec![b"garbage\r\n\r\n".to_vec()]);
+
+        let err = client.request_json("GET", "/info", None).err().unwrap();
+        assert!(matches!(err, Error::Api { status: 0, .. }));
+        daemon.join().unwrap();
+    }
+
+    #[test]
+    fn upgrade_returns_hijacked_stream() {
+        let (client, daemon) = fake_daemon(vec![
+            b"HTTP/1.1 101 UPGRADED\r\nConnection: Upgrade\r\nUpgrade: tcp\r\n\r\nraw output"
+                .to_vec(),
+        ]);
+
+        let (mut reader, _writer) = client
+            .upgrade("/exec/abc/start", &json!({"Detach": false}))
+            .unwrap();
+        let mut output = String::new();
+        reader.read_to_string(&mut output).unwrap();
+        assert_eq!(output, "raw output");
+
+        let requests = daemon.join().unwrap();
+        assert_eq!(requests[0].line, "POST /v1.41/exec/abc/start HTTP/1.1");
+    }
+
+    #[test]
+    fn upgrade_error_status_is_error() {
+        let body = r#"{"message":"No such exec instance: abc"}"#;
+        let (client, daemon) = fake_daemon(vec![
+            format!("HTTP/1.1 404 Not Found\r\n\r\n{body}").into_bytes(),
+        ]);
+
+        let err = client.upgrade("/exec/abc/start", &json!({})).err().unwrap();
+        assert!(matches!(err, Error::Api { status: 404, ref message } if message.contains("abc")));
+        daemon.join().unwrap();
+    }
+
+    #[test]
+    fn chunked_reader_handles_small_buffers() {
+        let data = b"3\r\nabc\r\n2\r\nde\r\n0\r\n\r\n";
+        let mut reader = ChunkedReader::new(&data[..]);
+        let mut out = Vec::new();
+        let mut buf = [0; 2];
+        loop {
+            let n = reader.read(&mut buf).unwrap();
+            if n == 0 {
+                break;
+            }
+            out.extend_from_slice(&buf[..n]);
+        }
+        assert_eq!(out, b"abcde");
+        // 終端チャンクの後は常にEOF
+        assert_eq!(reader.read(&mut buf).unwrap(), 0);
+    }
+
+    #[test]
+    fn chunked_reader_rejects_bad_input() {
+        let mut out = Vec::new();
+        let err = ChunkedReader::new(&b"zz\r\nabc\r\n"[..])
+            .read_to_end(&mut out)
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        let err = ChunkedReader::new(&b"5\r\nab"[..])
+            .read_to_end(&mut out)
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn encode_query_escapes_reserved_bytes() {
+        assert_eq!(encode_query("abc-_.~XYZ019"), "abc-_.~XYZ019");
+        assert_eq!(
+            encode_query(r#"{"label":["roxy.uuid=a b"]}"#),
+            "%7B%22label%22%3A%5B%22roxy.uuid%3Da%20b%22%5D%7D"
+        );
+        assert_eq!(encode_query("é"), "%C3%A9");
+    }
+}
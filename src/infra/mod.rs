@@ -1,2 +1,8 @@
-pub mod docker;
+pub mod compose;
+pub mod compose_cli;
+pub mod dispatch;
+pub mod docker_api;
+pub mod engine;
+pub mod picker;
+pub mod render;
 pub mod sqlite;
@@ -0,0 +1,20 @@
+use std::io;
+
+use dialoguer::FuzzySelect;
+use dialoguer::theme::ColorfulTheme;
+
+use crate::domain::repo::Error;
+
+// 候補を標準エラー出力に表示し、入力で絞り込んで選ばせる
+// 選ばれた候補の位置を返す (EscやCtrl-Cで中断された場合はNone)
+pub fn pick(prompt: &str, items: &[String]) -> Result<Option<usize>, Error> {
+    FuzzySelect::with_theme(&ColorfulTheme::default())
+        .with_prompt(prompt)
+        .items(items)
+        .default(0)
+        .interact_opt()
+        .map_err(|err| Error::Io {
+            path: None,
+            source: io::Error::from(err),
+        })
+}
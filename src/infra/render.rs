@@ -0,0 +1,227 @@
+use std::mem;
+use std::path::Path;
+
+use indexmap::IndexMap;
+use log::warn;
+use serde_yaml::Value;
+
+use crate::domain::repo::{EnvSpec, Error};
+use crate::util::{host_gid, host_uid};
+
+// テンプレートで使える変数の一覧を返す
+// プロジェクトの設定で定義された変数と同じ名前の組み込み変数がある場合は組み込み変数を優先する
+pub fn variables(env_spec: &EnvSpec) -> IndexMap<String, String> {
+    let mut variables = env_spec.config.variables.clone();
+
+    let builtins = [
+        ("project_name", env_spec.project_name.clone()),
+        ("project_path", env_spec.project_path.display().to_string()),
+        ("uuid", env_spec.uuid.to_string()),
+        ("host_uid", host_uid().to_string()),
+        ("host_gid", host_gid().to_string()),
+    ];
+    for (name, value) in builtins {
+        if variables.insert(name.to_string(), value).is_some() {
+            warn!("Template variable {name} is built-in and can't be overridden.");
+        }
+    }
+
+    variables
+}
+
+// テンプレート中の{{name}}を変数の値で置き換える
+// 名前として解釈できない{{...}}はそのまま残し、未定義の変数はまとめてエラーにする
+pub fn render(
+    template: &str,
+    variables: &IndexMap<String, String>,
+    path: &Path,
+) -> Result<String, Error> {
+    let mut unknown = Vec::new();
+    let out = substitute(template, variables, &mut unknown);
+    check_unknown(unknown, path)?;
+    Ok(out)
+}
+
+// yamlのテンプレートを解析してから、文字列のスカラー (キーを含む) 中の{{name}}を変数の値で置き換える
+// 置き換えた値はyamlとして解釈し直さないので、値に:や#、引用符、改行が含まれていてもそのまま文字列になる
+// {{name}}で始まる値はyamlのフロー形式と解釈されるので、テンプレートでは引用符で囲む必要がある
+pub fn render_yaml(
+    template: &str,
+    variables: &IndexMap<String, String>,
+    path: &Path,
+) -> Result<Value, Error> {
+    let mut value: Value = serde_yaml::from_str(template).map_err(Error::YamlSer)?;
+    let mut unknown = Vec::new();
+    substitute_value(&mut value, variables, &mut unknown);
+    check_unknown(unknown, path)?;
+    Ok(value)
+}
+
+fn substitute_value(
+    value: &mut Value,
+    variables: &IndexMap<String, String>,
+    unknown: &mut Vec<String>,
+) {
+    match value {
+        Value::String(s) => *s = substitute(s, variables, unknown),
+        Value::Sequence(seq) => {
+            for v in seq {
+                substitute_value(v, variables, unknown);
+            }
+        }
+        Value::Mapping(m) => {
+            for (mut k, mut v) in mem::take(m) {
+                substitute_value(&mut k, variables, unknown);
+                substitute_value(&mut v, variables, unknown);
+                m.insert(k, v);
+            }
+        }
+        Value::Tagged(tagged) => substitute_value(&mut tagged.value, variables, unknown),
+        Value::Null | Value::Bool(_) | Value::Number(_) => {}
+    }
+}
+
+fn substitute(
+    template: &str,
+    variables: &IndexMap<String, String>,
+    unknown: &mut Vec<String>,
+) -> String {
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find("{{") {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+
+        let Some(end) = after.find("}}") else {
+            out.push_str(&rest[start..]);
+            rest = "";
+            break;
+        };
+
+        let name = after[..end].trim();
+        if !is_variable_name(name) {
+            out.push_str("{{");
+            rest = after;
+            continue;
+        }
+
+        match variables.get(name) {
+            Some(value) => out.push_str(value),
+            None => {
+                if !unknown.iter().any(|n| n == name) {
+                    unknown.push(name.to_string());
+                }
+            }
+        }
+        rest = &after[end + 2..];
+    }
+    out.push_str(rest);
+    out
+}
+
+fn check_unknown(unknown: Vec<String>, path: &Path) -> Result<(), Error> {
+    if !unknown.is_empty() {
+        return Err(Error::UnknownTemplateVariables {
+            path: path.to_path_buf(),
+            names: unknown,
+        });
+    }
+    Ok(())
+}
+
+fn is_variable_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars(pairs: &[(&str, &str)]) -> IndexMap<String, String> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    fn render_compose(template: &str, value: &str) -> Value {
+        render_yaml(template, &vars(&[("v", value)]), Path::new("compose.yml")).unwrap()
+    }
+
+    const TEMPLATE: &str =
+        "services:\n  dev:\n    hostname: \"{{v}}\"\n    environment:\n      - VALUE={{ v }}\n";
+
+    #[test]
+    fn render_yaml_keeps_special_characters_in_values() {
+        for value in [
+            "a: b",
+            "a #comment",
+            "it's \"quoted\"",
+            "line1\nline2\n  privileged: true",
+            "{{v}}",
+        ] {
+            let rendered = render_compose(TEMPLATE, value);
+            let service = &rendered["services"]["dev"];
+            assert_eq!(service["hostname"], Value::String(value.to_string()));
+            assert_eq!(
+                service["environment"][0],
+                Value::String(format!("VALUE={value}"))
+            );
+            // 値の中身がyamlとして解釈されてキーが増えたりしない
+            assert_eq!(service.as_mapping().unwrap().len(), 2);
+        }
+    }
+
+    #[test]
+    fn render_yaml_substitutes_keys() {
+        let rendered = render_compose("services:\n  \"{{v}}\":\n    image: x\n", "a: b");
+        assert_eq!(rendered["services"]["a: b"]["image"], Value::from("x"));
+    }
+
+    #[test]
+    fn render_yaml_leaves_non_variables_and_other_scalars() {
+        let rendered = render_compose(
+            "fmt: \"{{.State}}\"\nport: 3333\ntty: true\nunclosed: \"{{v\"\n",
+            "x",
+        );
+        assert_eq!(rendered["fmt"], Value::from("{{.State}}"));
+        assert_eq!(rendered["port"], Value::from(3333));
+        assert_eq!(rendered["tty"], Value::from(true));
+        assert_eq!(rendered["unclosed"], Value::from("{{v"));
+    }
+
+    #[test]
+    fn render_reports_all_unknown_variables() {
+        let err = render_yaml(
+            "a: \"{{x}} {{y}} {{x}}\"\n",
+            &vars(&[]),
+            Path::new("compose.yml"),
+        )
+        .unwrap_err();
+        assert!(
+            matches!(err, Error::UnknownTemplateVariables { ref names, .. } if names == &["x", "y"])
+        );
+
+        let err = render("FROM {{image}}", &vars(&[]), Path::new("dockerfile")).unwrap_err();
+        assert!(matches!(err, Error::UnknownTemplateVariables { .. }));
+    }
+
+    #[test]
+    fn render_substitutes_text() {
+        let rendered = render(
+            "FROM {{ image }}\nRUN echo {{image}} {{not a name}}",
+            &vars(&[("image", "debian:12")]),
+            Path::new("dockerfile"),
+        )
+        .unwrap();
+        assert_eq!(
+            rendered,
+            "FROM debian:12\nRUN echo debian:12 {{not a name}}"
+        );
+    }
+}
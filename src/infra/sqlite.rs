@@ -1,9 +1,157 @@
+use std::collections::HashSet;
 use std::path::Path;
+use std::str::FromStr;
 
-use rusqlite::Connection;
+use indexmap::IndexMap;
+use rusqlite::{Connection, OpenFlags, Params};
 use uuid::Uuid;
 
-use crate::domain::repo::{EnvStore, Error};
+use crate::domain::config::ProjectConfig;
+use crate::domain::repo::{
+    ContainerId, ContainerInfo, EnvRecord, EnvSpec, EnvState, EnvStore, EnvTimestamps, Error,
+    RuntimeKind,
+};
+use crate::util::canonicalize;
+
+// EnvRecordを構築するために読み出す列
+const RECORD_COLUMNS: &str = "uuid, path, name, container_id, runtime, state, config, services, \
+     template_hash, image_id, created_at, last_entered_at, ports";
+
+// スキーマの移行処理 (n番目の処理を適用したデータベースのuser_versionはnになる)
+// 適用済みの処理は変更せず、スキーマを変えるときは末尾に追加する
+const MIGRATIONS: &[Migration] = &[migrate_v1, migrate_v2, migrate_v3, migrate_v4];
+
+type Migration = fn(&Connection) -> Result<(), Error>;
+
+// user_versionで移行を管理する前のスキーマ
+fn migrate_v1(connection: &Connection) -> Result<(), Error> {
+    connection
+        .execute(
+            "CREATE TABLE IF NOT EXISTS env_records (
+                        uuid          TEXT PRIMARY KEY,
+                        path  TEXT NOT NULL,
+                        name  TEXT NOT NULL,
+                        container_id  TEXT NOT NULL
+                     )",
+            (),
+        )
+        .map_err(Error::Db)?;
+    Ok(())
+}
+
+// ランタイム、状態、プロジェクトの設定、サービスのコンテナ、作成日時、最後に入った日時、
+// テンプレートのハッシュ、イメージ、公開しているポートを記録する
+// 既存の環境のポートは空のまま追加し、portコマンドで参照したときに設定ディレクトリから埋める
+fn migrate_v2(connection: &Connection) -> Result<(), Error> {
+    connection
+        .execute_batch(
+            "ALTER TABLE env_records ADD COLUMN runtime TEXT NOT NULL DEFAULT 'docker';
+             ALTER TABLE env_records ADD COLUMN state TEXT NOT NULL DEFAULT 'running';
+             ALTER TABLE env_records ADD COLUMN config TEXT NOT NULL DEFAULT '';
+             ALTER TABLE env_records ADD COLUMN services TEXT NOT NULL DEFAULT '';
+             ALTER TABLE env_records ADD COLUMN created_at TEXT;
+             ALTER TABLE env_records ADD COLUMN last_entered_at TEXT;
+             ALTER TABLE env_records ADD COLUMN template_hash TEXT;
+             ALTER TABLE env_records ADD COLUMN image_id TEXT;
+             ALTER TABLE env_records ADD COLUMN ports TEXT NOT NULL DEFAULT '[]';",
+        )
+        .map_err(Error::Db)
+}
+
+// 環境の名前を一意にする
+// 既に重複している名前は、古い環境から順に残し、それ以外に"-2"などを付けて区別する
+fn migrate_v3(connection: &Connection) -> Result<(), Error> {
+    let mut stmt = connection
+        .prepare("SELECT uuid, name FROM env_records ORDER BY created_at, rowid")
+        .map_err(Error::Db)?;
+    let rows = stmt
+        .query_map([], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+        })
+        .map_err(Error::Db)?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(Error::Db)?;
+
+    let mut used = rows
+        .iter()
+        .map(|(_, name)| name.clone())
+        .collect::<HashSet<_>>();
+    let mut kept = HashSet::new();
+    for (uuid, name) in rows {
+        if kept.insert(name.clone()) {
+            continue;
+        }
+        let renamed = (2..)
+            .map(|n| format!("{name}-{n}"))
+            .find(|candidate| !used.contains(candidate))
+            .unwrap();
+        connection
+            .execute(
+                "UPDATE env_records SET name = ?2 WHERE uuid = ?1",
+                rusqlite::params![uuid, renamed],
+            )
+            .map_err(Error::Db)?;
+        used.insert(renamed.clone());
+        kept.insert(renamed);
+    }
+
+    connection
+        .execute(
+            "CREATE UNIQUE INDEX IF NOT EXISTS env_records_name ON env_records (name)",
+            (),
+        )
+        .map_err(Error::Db)?;
+    Ok(())
+}
+
+// パスで検索できるように、記録されたプロジェクトのパスを正規化する
+// 以降は保存するときに正規化するので、この処理は一度だけ行えばよい
+fn migrate_v4(connection: &Connection) -> Result<(), Error> {
+    let mut stmt = connection
+        .prepare("SELECT uuid, path FROM env_records")
+        .map_err(Error::Db)?;
+    let rows = stmt
+        .query_map([], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+        })
+        .map_err(Error::Db)?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(Error::Db)?;
+    for (uuid, path) in rows {
+        let canonical = path_to_column(Path::new(&path));
+        if canonical != path {
+            connection
+                .execute(
+                    "UPDATE env_records SET path = ?2 WHERE uuid = ?1",
+                    rusqlite::params![uuid, canonical],
+                )
+                .map_err(Error::Db)?;
+        }
+    }
+    Ok(())
+}
+
+// path列に保存する文字列 (シンボリックリンクを経由したパスでも一致するように正規化する)
+fn path_to_column(path: &Path) -> String {
+    canonicalize(path).to_string_lossy().to_string()
+}
+
+// env_recordsから読み出した1行
+struct Row {
+    uuid: String,
+    path: String,
+    name: String,
+    container_id: String,
+    runtime: String,
+    state: String,
+    config: String,
+    services: String,
+    template_hash: Option<String>,
+    image_id: Option<String>,
+    created_at: Option<String>,
+    last_entered_at: Option<String>,
+    ports: String,
+}
 
 pub struct SqliteForContainerStore {
     connection: Connection,
@@ -12,215 +160,624 @@ pub struct SqliteForContainerStore {
 impl SqliteForContainerStore {
     pub fn new(database_path: &Path) -> Result<Self, Error> {
         // データベースに接続する (ファイルがなければ作成される)
-        let connection = match Connection::open(database_path) {
+        let mut connection = match Connection::open(database_path) {
             Ok(c) => c,
             Err(err) => {
-                return Err(Error::Db(err));
+                return Err(Error::DbConn {
+                    path: database_path.to_path_buf(),
+                    source: err,
+                });
             }
         };
 
-        // テーブルが未作成の場合は作成する
-        if let Err(err) = connection.execute(
-            "CREATE TABLE IF NOT EXISTS env_records (
-                        uuid          TEXT PRIMARY KEY,
-                        path  TEXT NOT NULL,
-                        name  TEXT NOT NULL,
-                        container_id  TEXT NOT NULL
-                     )",
-            (),
-        ) {
-            return Err(Error::Db(err));
+        // スキーマを最新の版に移行する
+        Self::migrate(&mut connection)?;
+
+        Ok(Self { connection })
+    }
+
+    // 既存のデータベースを読み出し専用で開く (シェルの補完のように頻繁に呼ばれる場合に使う)
+    // ファイルを作成したりスキーマを移行したりしないので、最新の版でなければエラーにする
+    pub fn open_read_only(database_path: &Path) -> Result<Self, Error> {
+        let connection = Connection::open_with_flags(
+            database_path,
+            OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+        )
+        .map_err(|err| Error::DbConn {
+            path: database_path.to_path_buf(),
+            source: err,
+        })?;
+
+        let version = connection
+            .query_row("PRAGMA user_version", [], |row| row.get::<_, usize>(0))
+            .map_err(Error::Db)?;
+        if version != MIGRATIONS.len() {
+            return Err(Error::UnsupportedSchema {
+                version,
+                supported: MIGRATIONS.len(),
+            });
         }
 
         Ok(Self { connection })
     }
 
-    // 文字列の組からEnvRecordを作成する
-    fn record_from_parts(
-        uuid_s: String,
-        path_s: String,
-        name_s: String,
-        container_id_s: String,
-    ) -> Result<crate::domain::repo::EnvRecord, Error> {
-        let uuid = uuid::Uuid::parse_str(&uuid_s).map_err(|err| Error::Uuid(err))?;
-        let spec = crate::domain::repo::EnvSpec {
+    // 未適用の移行処理を順に適用する (それぞれの処理は1つのトランザクションで行う)
+    fn migrate(connection: &mut Connection) -> Result<(), Error> {
+        let version = connection
+            .query_row("PRAGMA user_version", [], |row| row.get::<_, usize>(0))
+            .map_err(Error::Db)?;
+
+        // 新しいバージョンのroxyが作成したデータベースは扱えない
+        if version > MIGRATIONS.len() {
+            return Err(Error::UnsupportedSchema {
+                version,
+                supported: MIGRATIONS.len(),
+            });
+        }
+
+        for (i, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+            let tx = connection.transaction().map_err(Error::Db)?;
+            migration(&tx)?;
+            tx.pragma_update(None, "user_version", i + 1)
+                .map_err(Error::Db)?;
+            tx.commit().map_err(Error::Db)?;
+        }
+
+        Ok(())
+    }
+
+    // 読み出した行からEnvRecordを作成する
+    fn record_from_row(row: Row) -> Result<EnvRecord, Error> {
+        let uuid = uuid::Uuid::parse_str(&row.uuid).map_err(Error::Uuid)?;
+        let spec = EnvSpec {
             uuid,
-            project_path: std::path::PathBuf::from(path_s),
-            project_name: name_s,
+            project_path: std::path::PathBuf::from(row.path),
+            project_name: row.name,
+            config: ProjectConfig::from_toml(&row.config)?,
+            template_hash: row.template_hash,
+        };
+        let container_info = ContainerInfo {
+            container_id: ContainerId::from_str(&row.container_id),
+            services: Self::services_from_json(&row.services)?,
+            image_id: row.image_id,
+            runtime: RuntimeKind::from_str(&row.runtime)?,
+            state: EnvState::from_str(&row.state)?,
+            ports: serde_json::from_str(&row.ports).map_err(Error::Json)?,
         };
-        let container_info = crate::domain::repo::ContainerInfo {
-            container_id: crate::domain::repo::ContainerId::from_str(&container_id_s),
+        let timestamps = EnvTimestamps {
+            created_at: row.created_at,
+            last_entered_at: row.last_entered_at,
         };
-        Ok(crate::domain::repo::EnvRecord {
+        Ok(EnvRecord {
             spec,
             container_info,
+            timestamps,
         })
     }
+
+    // サービス名とコンテナidの組をJSONのオブジェクトとして保存する
+    fn services_to_json(services: &IndexMap<String, ContainerId>) -> Result<String, Error> {
+        let services = services
+            .iter()
+            .map(|(name, id)| (name.clone(), id.to_string()))
+            .collect::<IndexMap<_, _>>();
+        serde_json::to_string(&services).map_err(Error::Json)
+    }
+
+    fn services_from_json(services_s: &str) -> Result<IndexMap<String, ContainerId>, Error> {
+        // 列が追加される前に作成された環境は空文字列になっている
+        if services_s.is_empty() {
+            return Ok(IndexMap::new());
+        }
+        let services: IndexMap<String, String> =
+            serde_json::from_str(services_s).map_err(Error::Json)?;
+        Ok(services
+            .into_iter()
+            .map(|(name, id)| (name, ContainerId::from_str(&id)))
+            .collect())
+    }
+
+    // 条件に一致するEnvRecordをすべて取得する
+    fn query_records<P: Params>(
+        &self,
+        condition: &str,
+        params: P,
+    ) -> Result<Vec<EnvRecord>, Error> {
+        let mut stmt = self
+            .connection
+            .prepare(&format!(
+                "SELECT {RECORD_COLUMNS} FROM env_records {condition}"
+            ))
+            .map_err(Error::Db)?;
+
+        let rows = stmt
+            .query_map(params, |row| {
+                Ok(Row {
+                    uuid: row.get(0)?,
+                    path: row.get(1)?,
+                    name: row.get(2)?,
+                    container_id: row.get(3)?,
+                    runtime: row.get(4)?,
+                    state: row.get(5)?,
+                    config: row.get(6)?,
+                    services: row.get(7)?,
+                    template_hash: row.get(8)?,
+                    image_id: row.get(9)?,
+                    created_at: row.get(10)?,
+                    last_entered_at: row.get(11)?,
+                    ports: row.get(12)?,
+                })
+            })
+            .map_err(Error::Db)?;
+
+        let mut out = Vec::new();
+        for r in rows {
+            out.push(Self::record_from_row(r.map_err(Error::Db)?)?);
+        }
+        Ok(out)
+    }
 }
 
 impl EnvStore for SqliteForContainerStore {
     // EnvRecordを追加する
-    fn insert(&mut self, record: &crate::domain::repo::EnvRecord) -> Result<(), Error> {
+    fn insert(&mut self, record: &EnvRecord) -> Result<(), Error> {
         // EnvRecordの各フィールドを文字列に変換する
         let uuid = &record.spec.uuid.to_string();
-        let path = &record.spec.project_path.to_string_lossy().to_string();
+        let path = &path_to_column(&record.spec.project_path);
         let name = &record.spec.project_name;
         let container_id = &record.container_info.container_id.to_string();
+        let runtime = record.container_info.runtime.as_str();
+        let state = record.container_info.state.as_str();
+        let config = &record.spec.config.to_toml()?;
+        let services = &Self::services_to_json(&record.container_info.services)?;
+        let template_hash = &record.spec.template_hash;
+        let image_id = &record.container_info.image_id;
+        let ports = &serde_json::to_string(&record.container_info.ports).map_err(Error::Json)?;
+
+        // 名前は環境を一意に定めなければならない
+        self.ensure_name_available(name, record.spec.uuid, &record.spec.project_path)?;
 
+        // 作成日時はストアが記録する
         let mut stmt = self
             .connection
             .prepare(
-                "INSERT INTO env_records (uuid, path, name, container_id)
-                         VALUES (?1, ?2, ?3, ?4)",
+                "INSERT INTO env_records (uuid, path, name, container_id, runtime, state, config,
+                                         services, template_hash, image_id, ports, created_at)
+                         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, datetime('now'))",
             )
-            .map_err(|err| Error::Db(err))?;
+            .map_err(Error::Db)?;
 
-        stmt.execute(rusqlite::params![uuid, path, name, container_id])
-            .map_err(|err| Error::Db(err))?;
+        stmt.execute(rusqlite::params![
+            uuid,
+            path,
+            name,
+            container_id,
+            runtime,
+            state,
+            config,
+            services,
+            template_hash,
+            image_id,
+            ports
+        ])
+        .map_err(Error::Db)?;
 
         Ok(())
     }
 
-    fn find_by_path(
-        &mut self,
-        path: &Path,
-    ) -> Result<Vec<crate::domain::repo::EnvRecord>, crate::domain::repo::Error> {
-        let path_s = path.to_string_lossy().to_string();
-        let mut stmt = self
-            .connection
-            .prepare("SELECT uuid, path, name, container_id FROM env_records WHERE path = ?1")
-            .map_err(|err| Error::Db(err))?;
+    // path列には正規化したパスが保存されているので、正規化したパスで検索する
+    fn find_by_path(&mut self, path: &Path) -> Result<Vec<EnvRecord>, Error> {
+        self.query_records("WHERE path = ?1", rusqlite::params![path_to_column(path)])
+    }
 
-        let rows = stmt
-            .query_map(rusqlite::params![path_s], |row| {
-                Ok((
-                    row.get::<_, String>(0)?,
-                    row.get::<_, String>(1)?,
-                    row.get::<_, String>(2)?,
-                    row.get::<_, String>(3)?,
-                ))
-            })
-            .map_err(|err| Error::Db(err))?;
+    fn find_by_name(&mut self, name: String) -> Result<Vec<EnvRecord>, Error> {
+        self.query_records("WHERE name = ?1", rusqlite::params![name])
+    }
 
-        let mut out = Vec::new();
-        for r in rows {
-            let (u, p, n, c) = r.map_err(|err| Error::Db(err))?;
-            out.push(Self::record_from_parts(u, p, n, c)?);
+    fn ensure_name_available(&mut self, name: &str, uuid: Uuid, path: &Path) -> Result<(), Error> {
+        let existing = self
+            .find_by_name(name.to_string())?
+            .into_iter()
+            .find(|record| record.spec.uuid != uuid);
+        match existing {
+            Some(existing) => Err(Error::EnvConflict {
+                name: name.to_string(),
+                path: path.to_path_buf(),
+                existing: Some(existing.spec.uuid),
+            }),
+            None => Ok(()),
         }
-        Ok(out)
     }
 
-    fn find_by_name(
-        &mut self,
-        name: String,
-    ) -> Result<Vec<crate::domain::repo::EnvRecord>, crate::domain::repo::Error> {
-        let mut stmt = self
-            .connection
-            .prepare("SELECT uuid, path, name, container_id FROM env_records WHERE name = ?1")
-            .map_err(|err| Error::Db(err))?;
-
-        let rows = stmt
-            .query_map(rusqlite::params![name], |row| {
-                Ok((
-                    row.get::<_, String>(0)?,
-                    row.get::<_, String>(1)?,
-                    row.get::<_, String>(2)?,
-                    row.get::<_, String>(3)?,
-                ))
-            })
-            .map_err(|err| Error::Db(err))?;
+    fn find_by_uuid(&mut self, uuid: Uuid) -> Result<Vec<EnvRecord>, Error> {
+        let uuid_s = uuid.to_string();
+        self.query_records("WHERE uuid = ?1", rusqlite::params![uuid_s])
+    }
 
-        let mut out = Vec::new();
-        for r in rows {
-            let (u, p, n, c) = r.map_err(|err| Error::Db(err))?;
-            out.push(Self::record_from_parts(u, p, n, c)?);
-        }
-        Ok(out)
+    fn list(&mut self) -> Result<Vec<EnvRecord>, Error> {
+        self.query_records("", [])
     }
 
-    fn find_by_uuid(
+    fn update_container(
         &mut self,
         uuid: Uuid,
-    ) -> Result<Vec<crate::domain::repo::EnvRecord>, crate::domain::repo::Error> {
+        container_info: &ContainerInfo,
+    ) -> Result<usize, Error> {
         let uuid_s = uuid.to_string();
+        let container_id = container_info.container_id.to_string();
+        let services = Self::services_to_json(&container_info.services)?;
+        let ports = serde_json::to_string(&container_info.ports).map_err(Error::Json)?;
         let mut stmt = self
             .connection
-            .prepare("SELECT uuid, path, name, container_id FROM env_records WHERE uuid = ?1")
-            .map_err(|err| Error::Db(err))?;
-
-        let rows = stmt
-            .query_map(rusqlite::params![uuid_s], |row| {
-                Ok((
-                    row.get::<_, String>(0)?,
-                    row.get::<_, String>(1)?,
-                    row.get::<_, String>(2)?,
-                    row.get::<_, String>(3)?,
-                ))
-            })
-            .map_err(|err| Error::Db(err))?;
-
-        let mut out = Vec::new();
-        for r in rows {
-            let (u, p, n, c) = r.map_err(|err| Error::Db(err))?;
-            out.push(Self::record_from_parts(u, p, n, c)?);
-        }
-        Ok(out)
+            .prepare(
+                "UPDATE env_records SET container_id = ?2, runtime = ?3, state = ?4, services = ?5,
+                                        image_id = ?6, ports = ?7
+                         WHERE uuid = ?1",
+            )
+            .map_err(Error::Db)?;
+        stmt.execute(rusqlite::params![
+            uuid_s,
+            container_id,
+            container_info.runtime.as_str(),
+            container_info.state.as_str(),
+            services,
+            container_info.image_id,
+            ports
+        ])
+        .map_err(Error::Db)
     }
 
-    fn list(&mut self) -> Result<Vec<crate::domain::repo::EnvRecord>, crate::domain::repo::Error> {
+    fn update_spec(&mut self, uuid: Uuid, spec: &EnvSpec) -> Result<usize, Error> {
+        let uuid_s = uuid.to_string();
+        let config_s = spec.config.to_toml()?;
         let mut stmt = self
             .connection
-            .prepare("SELECT uuid, path, name, container_id FROM env_records")
-            .map_err(|err| Error::Db(err))?;
-
-        let rows = stmt
-            .query_map([], |row| {
-                Ok((
-                    row.get::<_, String>(0)?,
-                    row.get::<_, String>(1)?,
-                    row.get::<_, String>(2)?,
-                    row.get::<_, String>(3)?,
-                ))
-            })
-            .map_err(|err| Error::Db(err))?;
+            .prepare("UPDATE env_records SET config = ?2, template_hash = ?3 WHERE uuid = ?1")
+            .map_err(Error::Db)?;
+        stmt.execute(rusqlite::params![uuid_s, config_s, spec.template_hash])
+            .map_err(Error::Db)
+    }
 
-        let mut out = Vec::new();
-        for r in rows {
-            let (u, p, n, c) = r.map_err(|err| Error::Db(err))?;
-            out.push(Self::record_from_parts(u, p, n, c)?);
-        }
-        Ok(out)
+    fn update_entered(&mut self, uuid: Uuid) -> Result<usize, Error> {
+        let uuid_s = uuid.to_string();
+        let mut stmt = self
+            .connection
+            .prepare("UPDATE env_records SET last_entered_at = datetime('now') WHERE uuid = ?1")
+            .map_err(Error::Db)?;
+        stmt.execute(rusqlite::params![uuid_s]).map_err(Error::Db)
     }
 
-    fn remove_by_path(
-        &mut self,
-        path: &std::path::Path,
-    ) -> Result<usize, crate::domain::repo::Error> {
-        let path_s = path.to_string_lossy().to_string();
+    fn rename(&mut self, uuid: Uuid, name: &str) -> Result<usize, Error> {
+        let Some(record) = self.find_by_uuid(uuid)?.into_iter().next() else {
+            return Ok(0);
+        };
+        self.ensure_name_available(name, uuid, &record.spec.project_path)?;
+
+        let uuid_s = uuid.to_string();
         let mut stmt = self
             .connection
-            .prepare("DELETE FROM env_records WHERE path = ?1")
-            .map_err(|err| Error::Db(err))?;
-        stmt.execute(rusqlite::params![path_s])
-            .map_err(|err| Error::Db(err))
+            .prepare("UPDATE env_records SET name = ?2 WHERE uuid = ?1")
+            .map_err(Error::Db)?;
+        stmt.execute(rusqlite::params![uuid_s, name])
+            .map_err(Error::Db)
     }
 
-    fn remove_by_name(&mut self, name: String) -> Result<usize, crate::domain::repo::Error> {
+    fn update_state(&mut self, uuid: Uuid, state: EnvState) -> Result<usize, Error> {
+        let uuid_s = uuid.to_string();
         let mut stmt = self
             .connection
-            .prepare("DELETE FROM env_records WHERE name = ?1")
-            .map_err(|err| Error::Db(err))?;
-        stmt.execute(rusqlite::params![name])
-            .map_err(|err| Error::Db(err))
+            .prepare("UPDATE env_records SET state = ?2 WHERE uuid = ?1")
+            .map_err(Error::Db)?;
+        stmt.execute(rusqlite::params![uuid_s, state.as_str()])
+            .map_err(Error::Db)
     }
 
-    fn remove_by_uuid(&mut self, uuid: uuid::Uuid) -> Result<usize, crate::domain::repo::Error> {
+    fn remove_by_uuid(&mut self, uuid: uuid::Uuid) -> Result<usize, Error> {
         let uuid_s = uuid.to_string();
         let mut stmt = self
             .connection
             .prepare("DELETE FROM env_records WHERE uuid = ?1")
-            .map_err(|err| Error::Db(err))?;
-        stmt.execute(rusqlite::params![uuid_s])
-            .map_err(|err| Error::Db(err))
+            .map_err(Error::Db)?;
+        stmt.execute(rusqlite::params![uuid_s]).map_err(Error::Db)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::env;
+    use std::fs;
+    use std::path::PathBuf;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+
+    // テストごとに別のデータベースファイルを使う (dropされたときに削除する)
+    pub(crate) struct TempDb(pub PathBuf);
+
+    impl TempDb {
+        pub(crate) fn new() -> Self {
+            static COUNTER: AtomicUsize = AtomicUsize::new(0);
+            let path = env::temp_dir().join(format!(
+                "roxy-sqlite-test-{}-{}.db",
+                std::process::id(),
+                COUNTER.fetch_add(1, Ordering::SeqCst)
+            ));
+            let _ = fs::remove_file(&path);
+            Self(path)
+        }
+    }
+
+    impl Drop for TempDb {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    // pathに紐づいたnameという名前の環境の記録
+    pub(crate) fn record(path: &Path, name: &str) -> EnvRecord {
+        EnvRecord {
+            spec: EnvSpec {
+                uuid: Uuid::new_v4(),
+                project_path: path.to_path_buf(),
+                project_name: name.to_string(),
+                config: ProjectConfig::default(),
+                template_hash: None,
+            },
+            container_info: ContainerInfo {
+                container_id: ContainerId::from_str("cid"),
+                services: IndexMap::new(),
+                image_id: None,
+                runtime: RuntimeKind::DockerApi,
+                state: EnvState::Running,
+                ports: Vec::new(),
+            },
+            timestamps: EnvTimestamps::default(),
+        }
+    }
+
+    // user_versionで移行を管理する前のroxyが作成したデータベース
+    fn create_unversioned(db: &TempDb, rows: &[(&str, &str, &str)]) {
+        let connection = Connection::open(&db.0).unwrap();
+        connection
+            .execute_batch(
+                "CREATE TABLE env_records (
+                     uuid          TEXT PRIMARY KEY,
+                     path  TEXT NOT NULL,
+                     name  TEXT NOT NULL,
+                     container_id  TEXT NOT NULL
+                 )",
+            )
+            .unwrap();
+        for (uuid, path, name) in rows {
+            connection
+                .execute(
+                    "INSERT INTO env_records (uuid, path, name, container_id)
+                     VALUES (?1, ?2, ?3, 'cid')",
+                    rusqlite::params![uuid, path, name],
+                )
+                .unwrap();
+        }
+    }
+
+    fn user_version(db: &TempDb) -> usize {
+        Connection::open(&db.0)
+            .unwrap()
+            .query_row("PRAGMA user_version", [], |row| row.get(0))
+            .unwrap()
+    }
+
+    fn columns(db: &TempDb) -> Vec<String> {
+        let connection = Connection::open(&db.0).unwrap();
+        let mut stmt = connection
+            .prepare("SELECT name FROM pragma_table_info('env_records')")
+            .unwrap();
+        stmt.query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn upgrades_unversioned_database_to_latest() {
+        let db = TempDb::new();
+        let first = Uuid::new_v4().to_string();
+        let second = Uuid::new_v4().to_string();
+        create_unversioned(&db, &[(&first, "/work/a", "a"), (&second, "/work/b", "b")]);
+
+        let mut store = SqliteForContainerStore::new(&db.0).unwrap();
+        assert_eq!(user_version(&db), MIGRATIONS.len());
+
+        let records = store.list().unwrap();
+        assert_eq!(records.len(), 2);
+        let record = &store
+            .find_by_uuid(Uuid::parse_str(&first).unwrap())
+            .unwrap()[0];
+        assert_eq!(record.spec.project_name, "a");
+        assert_eq!(record.spec.project_path, PathBuf::from("/work/a"));
+        assert_eq!(record.container_info.runtime, RuntimeKind::Docker);
+        assert_eq!(record.container_info.state, EnvState::Running);
+        assert!(record.container_info.services.is_empty());
+        assert!(record.container_info.ports.is_empty());
+        assert_eq!(record.spec.template_hash, None);
+
+        let columns = columns(&db);
+        for column in [
+            "runtime",
+            "state",
+            "config",
+            "services",
+            "created_at",
+            "ports",
+        ] {
+            assert!(columns.iter().any(|c| c == column), "{column} is missing");
+        }
+    }
+
+    #[test]
+    fn reopening_migrated_database_is_noop() {
+        let db = TempDb::new();
+        create_unversioned(&db, &[(&Uuid::new_v4().to_string(), "/work/a", "a")]);
+
+        SqliteForContainerStore::new(&db.0).unwrap();
+        let mut store = SqliteForContainerStore::new(&db.0).unwrap();
+        assert_eq!(user_version(&db), MIGRATIONS.len());
+        assert_eq!(store.list().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn names_stay_unique_after_migration() {
+        let db = TempDb::new();
+        create_unversioned(&db, &[(&Uuid::new_v4().to_string(), "/work/a", "a")]);
+
+        let connection = {
+            SqliteForContainerStore::new(&db.0).unwrap();
+            Connection::open(&db.0).unwrap()
+        };
+        let duplicate = connection.execute(
+            "INSERT INTO env_records (uuid, path, name, container_id) VALUES ('x', '/work/x', 'a', 'c')",
+            (),
+        );
+        assert!(duplicate.is_err());
+    }
+
+    #[test]
+    fn read_only_open_requires_migrated_database() {
+        let db = TempDb::new();
+        assert!(SqliteForContainerStore::open_read_only(&db.0).is_err());
+        // 存在しないデータベースを作成しない
+        assert!(!db.0.exists());
+
+        create_unversioned(&db, &[(&Uuid::new_v4().to_string(), "/work/a", "a")]);
+        let err = SqliteForContainerStore::open_read_only(&db.0)
+            .err()
+            .unwrap();
+        assert!(matches!(err, Error::UnsupportedSchema { version: 0, .. }));
+        assert_eq!(user_version(&db), 0);
+
+        SqliteForContainerStore::new(&db.0).unwrap();
+        let mut store = SqliteForContainerStore::open_read_only(&db.0).unwrap();
+        assert_eq!(store.list().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn paths_are_stored_and_found_canonicalized() {
+        let db = TempDb::new();
+        let dir = env::temp_dir().join(format!("roxy-sqlite-path-{}", std::process::id()));
+        let project = dir.join("project");
+        let link = dir.join("link");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&project).unwrap();
+        std::os::unix::fs::symlink(&project, &link).unwrap();
+
+        let mut store = SqliteForContainerStore::new(&db.0).unwrap();
+        store.insert(&record(&link, "p")).unwrap();
+
+        let found = store.find_by_path(&project).unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(
+            found[0].spec.project_path,
+            fs::canonicalize(&project).unwrap()
+        );
+        assert_eq!(store.find_by_path(&link.join(".")).unwrap().len(), 1);
+        assert!(store.find_by_path(&dir).unwrap().is_empty());
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn migration_canonicalizes_recorded_paths() {
+        let db = TempDb::new();
+        let dir = env::temp_dir().join(format!("roxy-sqlite-migrate-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("project")).unwrap();
+        let recorded = dir.join("project").join("..").join("project");
+        create_unversioned(
+            &db,
+            &[
+                (&Uuid::new_v4().to_string(), recorded.to_str().unwrap(), "a"),
+                (&Uuid::new_v4().to_string(), "/nonexistent/roxy", "b"),
+            ],
+        );
+
+        let mut store = SqliteForContainerStore::new(&db.0).unwrap();
+        let found = store.find_by_path(&dir.join("project")).unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].spec.project_name, "a");
+        // 存在しないパスはそのまま残す
+        assert_eq!(
+            store.find_by_path(Path::new("/nonexistent/roxy")).unwrap()[0]
+                .spec
+                .project_name,
+            "b"
+        );
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn migration_renames_duplicate_names() {
+        let db = TempDb::new();
+        let uuids = (0..5)
+            .map(|_| Uuid::new_v4().to_string())
+            .collect::<Vec<_>>();
+        create_unversioned(
+            &db,
+            &[
+                (&uuids[0], "/work/1", "a"),
+                (&uuids[1], "/work/2", "a"),
+                (&uuids[2], "/work/3", "a-2"),
+                (&uuids[3], "/work/4", "a"),
+                (&uuids[4], "/work/5", "b"),
+            ],
+        );
+
+        let mut store = SqliteForContainerStore::new(&db.0).unwrap();
+        let names = uuids
+            .iter()
+            .map(|uuid| {
+                let uuid = Uuid::parse_str(uuid).unwrap();
+                store.find_by_uuid(uuid).unwrap()[0]
+                    .spec
+                    .project_name
+                    .clone()
+            })
+            .collect::<Vec<_>>();
+        // 古いものから順に残し、既に使われている名前は飛ばして番号を付ける
+        assert_eq!(names, ["a", "a-3", "a-2", "a-4", "b"]);
+    }
+
+    #[test]
+    fn insert_and_rename_reject_used_names() {
+        let db = TempDb::new();
+        let mut store = SqliteForContainerStore::new(&db.0).unwrap();
+        let first = record(Path::new("/work/1"), "a");
+        let second = record(Path::new("/work/2"), "b");
+        store.insert(&first).unwrap();
+        store.insert(&second).unwrap();
+
+        let err = store
+            .insert(&record(Path::new("/work/3"), "a"))
+            .unwrap_err();
+        assert!(
+            matches!(err, Error::EnvConflict { existing: Some(uuid), .. } if uuid == first.spec.uuid)
+        );
+
+        let err = store.rename(second.spec.uuid, "a").unwrap_err();
+        assert!(matches!(err, Error::EnvConflict { .. }));
+        // 自分自身の名前は使える
+        store
+            .ensure_name_available("b", second.spec.uuid, Path::new("/work/2"))
+            .unwrap();
+    }
+
+    #[test]
+    fn rejects_newer_schema() {
+        let db = TempDb::new();
+        Connection::open(&db.0)
+            .unwrap()
+            .pragma_update(None, "user_version", MIGRATIONS.len() + 1)
+            .unwrap();
+
+        let err = SqliteForContainerStore::new(&db.0).err().unwrap();
+        assert!(matches!(err, Error::UnsupportedSchema { .. }));
     }
 }
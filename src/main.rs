@@ -5,21 +5,22 @@ mod util;
 
 use log::error;
 use std::env;
-use std::path::PathBuf;
 use std::process::exit;
 
 use self::domain::repo::SharedResources;
 use self::util::fs_present;
 
 fn main() {
+    // シェルの補完から呼ばれた場合は候補を出力して終了する
+    cli::complete();
+
     // ロガーの初期化
     simple_logger::SimpleLogger::new()
         .with_level(log::LevelFilter::Debug)
         .init()
         .unwrap();
 
-    let mut shared_dir_path = env::home_dir().unwrap();
-    shared_dir_path.extend([".local", "share", "roxy"]);
+    let shared_dir_path = SharedResources::default_shared_dir_path().unwrap();
 
     // 共有ディレクトリが存在しているか確認する
     let shared_dir_presence = match fs_present(&shared_dir_path) {
@@ -48,12 +49,8 @@ fn main() {
         }
     };
 
-    let shared_resources = SharedResources {
-        shared_dir_path,
-        dockerfile_template_relative_path: PathBuf::from_iter(["template.dockerfile"]),
-        compose_template_relative_path: PathBuf::from_iter(["template.compose.yml"]),
-        database_relative_path: PathBuf::from_iter(["store.db"]),
-    };
+    let shared_resources = SharedResources::new(shared_dir_path);
 
-    cli::handle(&current_path, &shared_resources);
+    let code = cli::handle(&current_path, &shared_resources);
+    exit(code);
 }
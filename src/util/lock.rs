@@ -0,0 +1,49 @@
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::os::fd::AsRawFd;
+use std::path::Path;
+
+use log::info;
+
+// ファイルに対する排他ロック (dropされたときにファイルが閉じられて解放される)
+pub struct FileLock {
+    _file: File,
+}
+
+impl FileLock {
+    // ロックを取得する (他のプロセスが持っている場合は解放されるまで待つ)
+    pub fn acquire(path: &Path) -> io::Result<Self> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(path)?;
+
+        // 待つことになる場合は、止まっているように見えないように表示してから待つ
+        if let Err(err) = flock(&file, libc::LOCK_EX | libc::LOCK_NB) {
+            if err.kind() != io::ErrorKind::WouldBlock {
+                return Err(err);
+            }
+            info!("Waiting for another roxy command to finish...");
+            flock(&file, libc::LOCK_EX)?;
+        }
+
+        Ok(Self { _file: file })
+    }
+}
+
+fn flock(file: &File, operation: libc::c_int) -> io::Result<()> {
+    loop {
+        // SAFETY: fileは開いているファイルのディスクリプタを持っている
+        if unsafe { libc::flock(file.as_raw_fd(), operation) } == 0 {
+            return Ok(());
+        }
+        let err = io::Error::last_os_error();
+        if err.kind() != io::ErrorKind::Interrupted {
+            return Err(err);
+        }
+    }
+}
@@ -1,19 +1,14 @@
-use std::ffi::OsStr;
+pub mod lock;
+pub mod terminal;
+
+use std::env;
+use std::fs;
+use std::io;
 use std::path::{Path, PathBuf};
-use std::process::{Command, Output};
-use std::{env, fs, io, vec};
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use anyhow::{Result, anyhow};
 
-pub fn whoami() -> String {
-    let output: Output = Command::new("whoami")
-        .output()
-        .expect("failed to execute process");
-
-    let username: String = String::from_utf8(output.stdout).unwrap();
-    username
-}
-
 pub fn fs_present(path: &Path) -> Result<bool> {
     if let Err(err) = fs::metadata(path) {
         if let io::ErrorKind::NotFound = err.kind() {
@@ -26,28 +21,61 @@ pub fn fs_present(path: &Path) -> Result<bool> {
     Ok(true)
 }
 
-pub fn create_dir(path: &Path) -> Result<()> {
-    if let Err(err) = fs::create_dir(path) {
-        return Err(anyhow!("Failed to create directory: {err}"));
-    }
-
-    Ok(())
-}
-
 // pub fn force_create_dir(path: &Path) -> Result<()> {
 //     if let Err(err) = fs::create_dir
 // }
 
+// パスを正規化する (存在しないなどで正規化できない場合はそのまま返す)
+pub fn canonicalize(path: &Path) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
 pub fn get_entry_name(path: &Path) -> String {
     path.file_name().unwrap().to_string_lossy().to_string()
 }
 
-pub fn option_to_vec<T>(opt: Option<T>) -> Vec<T> {
-    let mut v = Vec::new();
+// このプロセスを実行しているユーザーの実UID
+pub fn host_uid() -> u32 {
+    // SAFETY: getuidは常に成功する
+    unsafe { libc::getuid() }
+}
 
-    if let Some(t) = opt {
-        v.push(t);
-    }
+// このプロセスを実行しているユーザーの実GID
+pub fn host_gid() -> u32 {
+    // SAFETY: getgidは常に成功する
+    unsafe { libc::getgid() }
+}
+
+// UTCの"YYYY-MM-DD HH:MM:SS"で表された日時から現在までの秒数を返す
+pub fn seconds_since(timestamp: &str) -> Option<u64> {
+    let (date, time) = timestamp.split_once(' ')?;
+    let mut date = date.splitn(3, '-').map(|v| v.parse::<i64>().ok());
+    let (year, month, day) = (date.next()??, date.next()??, date.next()??);
+    let mut time = time.splitn(3, ':').map(|v| v.parse::<i64>().ok());
+    let (hour, minute, second) = (time.next()??, time.next()??, time.next()??);
+
+    // 1970-01-01からの日数を求める (3月始まりの暦で閏日を年末に置く)
+    let (year, month) = if month <= 2 {
+        (year - 1, month + 9)
+    } else {
+        (year, month - 3)
+    };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * month + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146097 + day_of_era - 719468;
+
+    let timestamp = days * 86400 + hour * 3600 + minute * 60 + second;
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).ok()?.as_secs();
+    Some(now.saturating_sub(u64::try_from(timestamp).ok()?))
+}
+
+// PATH上に実行ファイルが存在するか確認する
+pub fn command_exists(name: &str) -> bool {
+    let Some(paths) = env::var_os("PATH") else {
+        return false;
+    };
 
-    v
+    env::split_paths(&paths).any(|dir| dir.join(name).is_file())
 }
@@ -0,0 +1,187 @@
+use std::io::{self, Write};
+use std::mem::MaybeUninit;
+use std::net::Shutdown;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::thread::{self, JoinHandle};
+
+// 端末をrawモードにし、dropされたときに元の設定に戻す
+pub struct RawMode {
+    fd: RawFd,
+    original: libc::termios,
+}
+
+impl RawMode {
+    pub fn enable(fd: RawFd) -> io::Result<Self> {
+        let mut termios = MaybeUninit::<libc::termios>::uninit();
+        // SAFETY: termiosはtcgetattrが成功した場合にのみ初期化済みとして扱う
+        let original = unsafe {
+            if libc::tcgetattr(fd, termios.as_mut_ptr()) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            termios.assume_init()
+        };
+
+        let mut raw = original;
+        // SAFETY: rawは有効なtermios構造体を指している
+        unsafe {
+            libc::cfmakeraw(&mut raw);
+            if libc::tcsetattr(fd, libc::TCSANOW, &raw) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+
+        Ok(Self { fd, original })
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        // SAFETY: originalはenableで取得した有効な設定である
+        unsafe {
+            libc::tcsetattr(self.fd, libc::TCSANOW, &self.original);
+        }
+    }
+}
+
+// 端末の大きさを(行数, 列数)で返す
+pub fn size(fd: RawFd) -> Option<(u16, u16)> {
+    let mut winsize = MaybeUninit::<libc::winsize>::uninit();
+    // SAFETY: winsizeはioctlが成功した場合にのみ初期化済みとして扱う
+    unsafe {
+        if libc::ioctl(fd, libc::TIOCGWINSZ, winsize.as_mut_ptr()) != 0 {
+            return None;
+        }
+        let winsize = winsize.assume_init();
+        Some((winsize.ws_row, winsize.ws_col))
+    }
+}
+
+// 入力をUnixStreamに転送するスレッド
+// 入力が閉じられたら書き込み側を閉じる
+// 入力の読み出しで止まったまま次のキー入力を奪わないように、dropされたときは自己パイプで終了させて待つ
+pub struct InputForwarder {
+    stop: OwnedFd,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl InputForwarder {
+    pub fn spawn(input: RawFd, mut writer: UnixStream) -> io::Result<Self> {
+        let mut fds = [0; 2];
+        // SAFETY: fdsはpipeが書き込む2つのfdの領域である
+        if unsafe { libc::pipe(fds.as_mut_ptr()) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: pipeが作成したfdは他から参照されていない
+        let (stop_reader, stop) =
+            unsafe { (OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) };
+
+        let handle = thread::spawn(move || {
+            let mut buf = [0u8; 4096];
+            loop {
+                let mut pollfds = [
+                    libc::pollfd {
+                        fd: input,
+                        events: libc::POLLIN,
+                        revents: 0,
+                    },
+                    libc::pollfd {
+                        fd: stop_reader.as_raw_fd(),
+                        events: libc::POLLIN,
+                        revents: 0,
+                    },
+                ];
+                // SAFETY: pollfdsは有効なpollfdの配列である
+                if unsafe { libc::poll(pollfds.as_mut_ptr(), 2, -1) } < 0 {
+                    if io::Error::last_os_error().kind() == io::ErrorKind::Interrupted {
+                        continue;
+                    }
+                    return;
+                }
+                if pollfds[1].revents != 0 {
+                    return;
+                }
+                if pollfds[0].revents == 0 {
+                    continue;
+                }
+
+                // 標準入力のバッファに読み残さないように、fdから直接読み出す
+                // SAFETY: bufはbuf.len()バイトの書き込み可能な領域である
+                let len = unsafe { libc::read(input, buf.as_mut_ptr().cast(), buf.len()) };
+                if len < 0 {
+                    if io::Error::last_os_error().kind() == io::ErrorKind::Interrupted {
+                        continue;
+                    }
+                    break;
+                }
+                if len == 0 || writer.write_all(&buf[..len as usize]).is_err() {
+                    break;
+                }
+            }
+            let _ = writer.shutdown(Shutdown::Write);
+        });
+
+        Ok(Self {
+            stop,
+            handle: Some(handle),
+        })
+    }
+}
+
+impl Drop for InputForwarder {
+    fn drop(&mut self) {
+        // SAFETY: stopはこの構造体が所有する有効なfdである
+        unsafe {
+            libc::write(self.stop.as_raw_fd(), [0u8].as_ptr().cast(), 1);
+        }
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use super::*;
+
+    fn pipe() -> (OwnedFd, OwnedFd) {
+        let mut fds = [0; 2];
+        // SAFETY: fdsはpipeが書き込む2つのfdの領域である
+        assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+        // SAFETY: pipeが作成したfdは他から参照されていない
+        unsafe { (OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) }
+    }
+
+    #[test]
+    fn forwards_input_until_eof() {
+        let (input, input_writer) = pipe();
+        let (writer, mut reader) = UnixStream::pair().unwrap();
+        let forwarder = InputForwarder::spawn(input.as_raw_fd(), writer).unwrap();
+
+        let mut input_writer = std::fs::File::from(input_writer);
+        input_writer.write_all(b"hello").unwrap();
+        drop(input_writer);
+
+        // 入力が閉じられると書き込み側も閉じられる
+        let mut forwarded = Vec::new();
+        reader.read_to_end(&mut forwarded).unwrap();
+        assert_eq!(forwarded, b"hello");
+        drop(forwarder);
+    }
+
+    #[test]
+    fn drop_stops_thread_waiting_for_input() {
+        // 何も書き込まれない入力を読み出している途中でも、dropすれば終了する
+        let (input, _input_writer) = pipe();
+        let (writer, mut reader) = UnixStream::pair().unwrap();
+        let forwarder = InputForwarder::spawn(input.as_raw_fd(), writer).unwrap();
+        drop(forwarder);
+
+        // スレッドが終了してwriterが閉じられている
+        let mut forwarded = Vec::new();
+        reader.read_to_end(&mut forwarded).unwrap();
+        assert!(forwarded.is_empty());
+    }
+}
@@ -1,4 +1,6 @@
 SHARED_PATH="$HOME/.local/share/roxy"
+STATE_PATH="${XDG_STATE_HOME:-$HOME/.local/state}/roxy"
 
 rm -rf $SHARED_PATH
+rm -rf $STATE_PATH
 rm /usr/local/bin/roxy